@@ -1,9 +1,10 @@
-// Swaps in the correct memory.x file based on the selected chip by way of --feature flag at build time.
+// Swaps in the correct memory.x file based on the selected chip by way of --feature flag at build time
+// and bakes the BTHP_* environment variables into `build_config.rs`.
 
 use std::env;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn linker_data() -> &'static [u8] {
     #[cfg(feature = "nrf52832")]
@@ -19,6 +20,198 @@ fn linker_data() -> &'static [u8] {
     &[0]
 }
 
+/// Settings that can be baked in at build time; these become the defaults in `config::Config`.
+/// Each one is `(variable, default)`.
+const ADV_INTERVAL_MS: (&str, u32) = ("BTHP_ADV_INTERVAL_MS", 6000);
+const TX_POWER: (&str, i8) = ("BTHP_TX_POWER", 0);
+const NAME_PREFIX: (&str, &str) = ("BTHP_NAME_PREFIX", "BTHPT_");
+const ON_SECS: (&str, u16) = ("BTHP_ON_SECS", 10);
+const OFF_SECS: (&str, u16) = ("BTHP_OFF_SECS", 10);
+const BATTERY_PROFILE: (&str, &str) = ("BTHP_BATTERY_PROFILE", "linear");
+const NAME_SUFFIX_BYTES: (&str, u8) = ("BTHP_NAME_SUFFIX_BYTES", 2);
+const NAME_SOURCE: (&str, &str) = ("BTHP_NAME_SOURCE", "address");
+const NAME_CASE: (&str, &str) = ("BTHP_NAME_CASE", "lower");
+const JITTER_PERCENT: (&str, u8) = ("BTHP_JITTER_PERCENT", 10);
+const SHELF_AFTER_MINS: (&str, u16) = ("BTHP_SHELF_AFTER_MINS", 0);
+const ADV_CHANNELS: (&str, &str) = ("BTHP_ADV_CHANNELS", "37,38,39");
+const ACTIVITY_RESET_HOURS: (&str, u16) = ("BTHP_ACTIVITY_RESET_HOURS", 24);
+
+/// Must match `config::NAME_PREFIX_LEN`
+const NAME_PREFIX_LEN: usize = 8;
+
+/// Reads `var`, falling back to `default` if it isn't set. Anything that doesn't parse fails the build.
+fn env_or<T: std::str::FromStr + ToString>((var, default): (&str, T)) -> T {
+    println!("cargo:rerun-if-env-changed={}", var);
+    match env::var(var) {
+        Ok(val) => val.trim().parse().unwrap_or_else(|_| {
+            panic!(
+                "{}={:?} is not a valid value; default is {}",
+                var,
+                val,
+                default.to_string()
+            )
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Generates `$OUT_DIR/build_config.rs` from the `BTHP_*` environment variables.
+fn build_config(out: &Path) {
+    let adv_interval_ms = env_or(ADV_INTERVAL_MS);
+    // The softdevice wants 0.625ms units and won't go past 16384 of them (10.24 seconds)
+    // or below 32 of them (20ms) for non-connectable advertising.
+    if !(20..=10240).contains(&adv_interval_ms) {
+        panic!(
+            "{}={} is out of range; must be between 20 and 10240 ms",
+            ADV_INTERVAL_MS.0, adv_interval_ms
+        );
+    }
+
+    let adv_channels = env_or((ADV_CHANNELS.0, ADV_CHANNELS.1.to_string()));
+    let mut channels = [false; 3];
+    for channel in adv_channels.split(',') {
+        match channel.trim().parse::<usize>() {
+            Ok(c @ 37..=39) => channels[c - 37] = true,
+            _ => panic!(
+                "{}={:?} must be a comma separated list of 37, 38 and 39",
+                ADV_CHANNELS.0, adv_channels
+            ),
+        }
+    }
+    let [ch37, ch38, ch39] = channels;
+
+    let tx_power = env_or(TX_POWER);
+    let mut supported_tx_power = vec![-40, -20, -16, -12, -8, -4, 0];
+    if env::var_os("CARGO_FEATURE_NRF52832").is_some() {
+        supported_tx_power.extend([3, 4]);
+    }
+    if !supported_tx_power.contains(&tx_power) {
+        panic!(
+            "{}={} is not supported by the radio; must be one of {:?} dBm",
+            TX_POWER.0, tx_power, supported_tx_power
+        );
+    }
+
+    let name_prefix = env_or((NAME_PREFIX.0, NAME_PREFIX.1.to_string()));
+    if name_prefix.len() > NAME_PREFIX_LEN || !name_prefix.bytes().all(|b| b.is_ascii_graphic()) {
+        panic!(
+            "{}={:?} must be at most {} printable ASCII characters (no spaces)",
+            NAME_PREFIX.0, name_prefix, NAME_PREFIX_LEN
+        );
+    }
+    let mut name_prefix_bytes = [0u8; NAME_PREFIX_LEN];
+    name_prefix_bytes[..name_prefix.len()].copy_from_slice(name_prefix.as_bytes());
+
+    let name_source = env_or((NAME_SOURCE.0, NAME_SOURCE.1.to_string()));
+    let (name_source, source_bytes, source_name) = match name_source.to_lowercase().as_str() {
+        "address" => ("Address", 6, "BLE address"),
+        "device_id" => ("DeviceId", 8, "FICR DEVICEID"),
+        _ => panic!(
+            "{}={:?} is not a known source; must be one of address, device_id",
+            NAME_SOURCE.0, name_source
+        ),
+    };
+    // With the longest prefix this is still well inside `encoding::MAX_NAME_LEN`; next to a full
+    // BTHome payload the name goes out shortened
+    let name_suffix_bytes = env_or(NAME_SUFFIX_BYTES);
+    if name_suffix_bytes > source_bytes {
+        panic!(
+            "{}={} is more than the {} bytes in the {}",
+            NAME_SUFFIX_BYTES.0, name_suffix_bytes, source_bytes, source_name
+        );
+    }
+    let name_case = env_or((NAME_CASE.0, NAME_CASE.1.to_string()));
+    let name_case = match name_case.to_lowercase().as_str() {
+        "lower" => "Lower",
+        "upper" => "Upper",
+        _ => panic!(
+            "{}={:?} must be one of lower, upper",
+            NAME_CASE.0, name_case
+        ),
+    };
+
+    let on_secs = env_or(ON_SECS);
+    if on_secs == 0 {
+        panic!("{}=0 would mean never advertising", ON_SECS.0);
+    }
+    // scheduler::MAX_PHASE
+    if on_secs > 30 * 60 {
+        panic!(
+            "{}={} is too long; must be at most 1800 (30 minutes)",
+            ON_SECS.0, on_secs
+        );
+    }
+    let off_secs = env_or(OFF_SECS);
+    if off_secs == 0 {
+        panic!("{}=0 would never sleep between windows", OFF_SECS.0);
+    }
+    // scheduler::MAX_PHASE again; the scheduler would quietly cut anything longer down to it
+    if off_secs > 30 * 60 {
+        panic!(
+            "{}={} is too long; must be at most 1800 (30 minutes)",
+            OFF_SECS.0, off_secs
+        );
+    }
+    // Any more and a short sleep could all but disappear
+    let jitter_percent = env_or(JITTER_PERCENT);
+    if jitter_percent > 50 {
+        panic!(
+            "{}={} is too much; must be at most 50",
+            JITTER_PERCENT.0, jitter_percent
+        );
+    }
+
+    let shelf_after_mins = env_or(SHELF_AFTER_MINS);
+    let activity_reset_hours = env_or(ACTIVITY_RESET_HOURS);
+    if activity_reset_hours == 0 {
+        panic!(
+            "{}=0 would reset the count before it got anywhere",
+            ACTIVITY_RESET_HOURS.0
+        );
+    }
+
+    let battery_profile = env_or((BATTERY_PROFILE.0, BATTERY_PROFILE.1.to_string()));
+    let battery_profile = match battery_profile.to_lowercase().as_str() {
+        "linear" => "Linear",
+        "cr2032" => "Cr2032",
+        _ => panic!(
+            "{}={:?} is not a known profile; must be one of linear, cr2032",
+            BATTERY_PROFILE.0, battery_profile
+        ),
+    };
+
+    let generated = format!(
+        "// Generated by build.rs from the BTHP_* environment variables; do not edit.
+
+pub const ADV_INTERVAL: AdvInterval = AdvInterval::from_millis({adv_interval_ms});
+/// {adv_channels:?}
+pub const ADV_CHANNELS: Channels = Channels {{
+    ch37: {ch37},
+    ch38: {ch38},
+    ch39: {ch39},
+}};
+pub const TX_POWER: TxPower = TxPower::from_dbm({tx_power});
+/// {name_prefix:?}, padded with 0
+pub const NAME_PREFIX: [u8; {NAME_PREFIX_LEN}] = {name_prefix_bytes:?};
+/// The same as a string; what's used if the stored prefix isn't valid
+pub const NAME_PREFIX_STR: &str = {name_prefix:?};
+pub const NAME_SUFFIX_BYTES: u8 = {name_suffix_bytes};
+pub const NAME_SOURCE: NameSource = NameSource::{name_source};
+pub const NAME_CASE: HexCase = HexCase::{name_case};
+pub const ADV_WINDOW: AdvWindow = AdvWindow::from_secs({on_secs});
+pub const OFF_SECS: u16 = {off_secs};
+/// Advertising windows and sleeps vary by up to this much either way
+pub const JITTER_PERCENT: u8 = {jitter_percent};
+/// Shelf mode after this many minutes without motion; 0 for never
+pub const SHELF_AFTER_MINS: u16 = {shelf_after_mins};
+/// How often the active minutes count goes back to zero
+pub const ACTIVITY_RESET_HOURS: u16 = {activity_reset_hours};
+pub const BATTERY_PROFILE: BatteryProfile = BatteryProfile::{battery_profile};
+"
+    );
+    std::fs::write(out.join("build_config.rs"), generated).unwrap();
+}
+
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
@@ -45,4 +238,23 @@ fn main() {
         println!("cargo:rustc-env=CARGO_PKG_VERSION={}", val);
     }
     println!("cargo:rerun-if-env-changed=RELEASE_VERSION");
+
+    production();
+    build_config(out);
+}
+
+/// `production` builds drop the logger, so the log calls have to go too; only DEFMT_LOG can do that.
+fn production() {
+    println!("cargo:rerun-if-env-changed=DEFMT_LOG");
+    if env::var_os("CARGO_FEATURE_PRODUCTION").is_none() {
+        return;
+    }
+    match env::var("DEFMT_LOG") {
+        Ok(level) if level.trim() == "off" => {}
+        level => panic!(
+            "the production feature needs DEFMT_LOG=off (got {:?}); set it when building, \
+             it takes precedence over .cargo/config.toml",
+            level.ok()
+        ),
+    }
 }
@@ -10,29 +10,41 @@ MEMORY
   */
   
   SOFTDEVICE : ORIGIN = 0x00000000, LENGTH = 100K
-  FLASH : ORIGIN = 0x00019000, LENGTH = 192K - 100K
+  FLASH : ORIGIN = 0x00019000, LENGTH = 192K - 100K - 4K
+
+  /* Last page of flash is reserved for the config record; see src/config.rs */
+  CONFIG : ORIGIN = 192K - 4K, LENGTH = 4K
   
   /* 
     24K RAM total -> 24*1024 -> 24576 => 0x6000
     
     Note that the exact address changes based on Config of soft-device.
 
-    After quite some testing / tuning, the lowest possible ram use that I could get
-    with the s112@7.0 is:
-
-      - advertise-only program: 0x11b8 bytes
-          0x6000 - 0x11b8 = 20040 bytes left over
-      
-      Even with the absolute bare minimum soft-device config, the 810 just doesn't
-        have enough RAM to run the soft-device and and of the more sophisticated programs
-        at the same time.
-      For this reason, pivoting to the 832 as it's only slightly more expensive but has 2.7 times
-        the RAM!
+    With the s112@7.0, an advertise-only program needs 0x11b8 bytes; that one was measured.
+    ble_advertise_timer also has the config window: one peripheral connection and a 1K GATT
+    attribute table (see runtime.rs), which needs more. The other binaries don't ask for a
+    connection (runtime::Builder defaults to advertise-only) so they still need only 0x11b8 and fit
+    under the same origin; they just leave some RAM unused.
+    TODO: 0x1a00 is an estimate (0x11b8, plus the bigger attribute table, plus one link's buffers),
+    not a measurement. nrf-softdevice logs the exact figure at boot when this is off in either
+    direction; put that here.
+
+    RAM budget for ble_advertise_timer, lowest address first:
+
+      softdevice          0x1a00  (6.5K, estimate; see above)
+      .data / .bss        task arena (3K, the nrf52810 feature in Cargo.toml),
+                          RTT buffer (1K; none with the production feature),
+                          statics (signals, channels, counters; well under 1K, not measured)
+      stack               everything else, about 12K; grows down from the top of RAM
+
+    Check .data / .bss with `cargo size --release --bin ble_advertise_timer --features <board>`.
    */
-  RAM : ORIGIN = 0x200011b8, LENGTH = 24K - 0x11b8
+  RAM : ORIGIN = 0x20001a00, LENGTH = 24K - 0x1a00
 
 }
 
+__config_start = ORIGIN(CONFIG);
+
 /*
   This allows us to ship the nordic softdevice binary as part of the
   compiled binary.
@@ -17,13 +17,21 @@ MEMORY
   */
   SOFTDEVICE : ORIGIN = 0x00000000, LENGTH = 100K
   
-  FLASH : ORIGIN = 0x00000000 + 100K, LENGTH = 512K - 100K
-  
-  RAM : ORIGIN = 0x200011b8, LENGTH = 0x10000 - 0x11b8
+  FLASH : ORIGIN = 0x00000000 + 100K, LENGTH = 512K - 100K - 4K
+
+  /* Last page of flash is reserved for the config record; see src/config.rs */
+  CONFIG : ORIGIN = 512K - 4K, LENGTH = 4K
+
+  /* 0x11b8 was the advertise-only figure. The config window (connectable, bigger attribute table)
+  needs more; 0x2000 is rounded up and not tuned yet. Check the softdevice's RAM message at boot.
+  */
+  RAM : ORIGIN = 0x20002000, LENGTH = 0x10000 - 0x2000
 
 }
 
 
+__config_start = ORIGIN(CONFIG);
+
 SECTIONS {
   .softdevice :
     {
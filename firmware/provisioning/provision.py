@@ -0,0 +1,160 @@
+#!/usr/bin/env python3
+"""Makes a per-device Intel HEX overlay for the UICR CUSTOMER registers.
+
+Flash the common firmware image as usual, then this on top of it (or pass --base to get a
+single file with both). The layout has to match src/uicr.rs.
+
+No dependencies beyond the standard library.
+"""
+
+import argparse
+import re
+import struct
+import sys
+
+# UICR CUSTOMER[0]; same place on the 52810 and 52832
+UICR_CUSTOMER = 0x1000_1080
+CUSTOMER_LEN = 32
+
+# CUSTOMER[0]
+FACTORY_RESET_MAGIC = 0x5445_5352  # "RSET"
+
+# CUSTOMER[1..]
+PROVISIONING_MAGIC = b"BU"
+PROVISIONING_VERSION = 1
+BODY_LEN = 32
+
+HAS_BIND_KEY = 1 << 0
+HAS_NAME_PREFIX = 1 << 1
+HAS_BATTERY_PROFILE = 1 << 2
+HAS_STATIC_ADDRESS = 1 << 3
+
+NAME_PREFIX_LEN = 8
+BATTERY_PROFILES = {"linear": 0, "cr2032": 1}
+
+
+def crc16(data: bytes) -> int:
+    """CRC-16/CCITT-FALSE; same as src/util/crc.rs."""
+    crc = 0xFFFF
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021) if crc & 0x8000 else (crc << 1)
+            crc &= 0xFFFF
+    return crc
+
+
+def parse_bind_key(value: str) -> bytes:
+    key = bytes.fromhex(value)
+    if len(key) != 16:
+        raise argparse.ArgumentTypeError("bind key must be 32 hex digits")
+    return key
+
+
+def parse_name_prefix(value: str) -> bytes:
+    if len(value) > NAME_PREFIX_LEN or not re.fullmatch(r"[!-~]*", value):
+        raise argparse.ArgumentTypeError(
+            f"name prefix must be at most {NAME_PREFIX_LEN} printable ASCII characters (no spaces)"
+        )
+    return value.encode("ascii").ljust(NAME_PREFIX_LEN, b"\0")
+
+
+def parse_static_address(value: str) -> bytes:
+    """Written the usual way, most significant byte first; stored the other way around."""
+    if not re.fullmatch(r"([0-9a-fA-F]{2}:){5}[0-9a-fA-F]{2}", value):
+        raise argparse.ArgumentTypeError("static address must look like e2:db:e8:62:67:0d")
+    address = bytes.fromhex(value.replace(":", ""))
+    random = int.from_bytes(address, "big")
+    random_bits = random & ((1 << 46) - 1)
+    if random >> 46 != 0b11 or random_bits in (0, (1 << 46) - 1):
+        raise argparse.ArgumentTypeError(
+            "not a static random address; the top two bits must be set "
+            "and the rest can't be all 0 or all 1"
+        )
+    return address[::-1]
+
+
+def provisioning_record(args) -> bytes:
+    present = 0
+    body = bytearray(BODY_LEN)
+    if args.bind_key is not None:
+        present |= HAS_BIND_KEY
+        body[1:17] = args.bind_key
+    if args.name_prefix is not None:
+        present |= HAS_NAME_PREFIX
+        body[17:25] = args.name_prefix
+    if args.battery_profile is not None:
+        present |= HAS_BATTERY_PROFILE
+        body[25] = BATTERY_PROFILES[args.battery_profile]
+    if args.static_address is not None:
+        present |= HAS_STATIC_ADDRESS
+        body[26:32] = args.static_address
+    body[0] = present
+
+    record = PROVISIONING_MAGIC + bytes([PROVISIONING_VERSION, BODY_LEN]) + bytes(body)
+    return record + struct.pack("<H", crc16(record))
+
+
+def hex_record(kind: int, address: int, data: bytes) -> str:
+    raw = bytes([len(data)]) + struct.pack(">H", address) + bytes([kind]) + data
+    checksum = (-sum(raw)) & 0xFF
+    return ":" + (raw + bytes([checksum])).hex().upper()
+
+
+def to_intel_hex(base: int, data: bytes) -> list:
+    """Data records for `data` at `base`; no EOF record so the result can be appended to."""
+    lines = [hex_record(0x04, 0, struct.pack(">H", base >> 16))]
+    for offset in range(0, len(data), 16):
+        lines.append(hex_record(0x00, (base + offset) & 0xFFFF, data[offset : offset + 16]))
+    return lines
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("--bind-key", type=parse_bind_key, help="32 hex digits")
+    parser.add_argument("--name-prefix", type=parse_name_prefix, help="overrides BTHP_NAME_PREFIX")
+    parser.add_argument(
+        "--battery-profile",
+        choices=BATTERY_PROFILES,
+        help="overrides BTHP_BATTERY_PROFILE",
+    )
+    parser.add_argument(
+        "--static-address",
+        type=parse_static_address,
+        help="take over another tag's identity, e.g. e2:db:e8:62:67:0d",
+    )
+    parser.add_argument(
+        "--factory-reset",
+        action="store_true",
+        help="ask for a factory reset on the next boot",
+    )
+    parser.add_argument("--base", help="firmware .hex to prepend; output is then a single image")
+    parser.add_argument("-o", "--output", help="defaults to stdout")
+    args = parser.parse_args()
+
+    # CUSTOMER[0] is left alone (erased) unless asked for
+    start = UICR_CUSTOMER if args.factory_reset else UICR_CUSTOMER + 4
+    customer = bytearray()
+    if args.factory_reset:
+        customer += struct.pack("<I", FACTORY_RESET_MAGIC)
+    customer += provisioning_record(args)
+    # UICR is written a word at a time; pad with erased flash
+    customer += b"\xff" * (-len(customer) % 4)
+    assert start + len(customer) <= UICR_CUSTOMER + CUSTOMER_LEN * 4
+
+    lines = []
+    if args.base:
+        with open(args.base) as f:
+            # Everything but the base image's EOF record
+            lines = [line.strip() for line in f if line.strip() and line.strip() != ":00000001FF"]
+    lines += to_intel_hex(start, bytes(customer))
+    lines.append(hex_record(0x01, 0, b""))
+
+    out = open(args.output, "w") if args.output else sys.stdout
+    out.write("\n".join(lines) + "\n")
+    if args.output:
+        out.close()
+
+
+if __name__ == "__main__":
+    main()
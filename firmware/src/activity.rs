@@ -0,0 +1,157 @@
+//! Turns accelerometer motion interrupts into "active minutes".
+//! Any minute with at least one motion interrupt counts as one active minute.
+//! The count lives in RAM so it keeps growing across advertising gaps; it is only
+//! cleared when the reset period rolls over.
+
+use embassy_time::{Duration, Instant};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+pub struct ActivityCounter {
+    reset_period: Duration,
+    period_start: Instant,
+    last_active_minute: Option<u64>,
+    /// Boot counts as motion so a tag that never moves still has somewhere to count from
+    last_motion: Instant,
+    active_minutes: u16,
+}
+
+impl ActivityCounter {
+    /// `const` so the counter can live in a `static` shared between tasks.
+    pub const fn new(reset_period: Duration) -> Self {
+        Self {
+            reset_period,
+            period_start: Instant::from_ticks(0),
+            last_active_minute: None,
+            last_motion: Instant::from_ticks(0),
+            active_minutes: 0,
+        }
+    }
+
+    /// Call for each motion interrupt from the accelerometer.
+    pub fn record_motion(&mut self, now: Instant) {
+        self.roll_over(now);
+        self.last_motion = now;
+        let minute = now.as_secs() / 60;
+        if self.last_active_minute != Some(minute) {
+            self.last_active_minute = Some(minute);
+            self.active_minutes = self.active_minutes.saturating_add(1);
+        }
+    }
+
+    /// Picks the count back up after a restart; the period starts over from boot.
+    pub fn restore(&mut self, active_minutes: u16) {
+        self.active_minutes = active_minutes;
+    }
+
+    /// Active minutes since the start of the current reset period.
+    pub fn active_minutes(&mut self, now: Instant) -> u16 {
+        self.roll_over(now);
+        self.active_minutes
+    }
+
+    /// How long it's been since the last motion interrupt (or since boot, if there hasn't been one).
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        now.duration_since(self.last_motion)
+    }
+
+    fn roll_over(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.period_start);
+        if elapsed < self.reset_period {
+            return;
+        }
+        // We may have slept through more than one period; keep the period boundaries aligned to boot
+        let periods = elapsed.as_ticks() / self.reset_period.as_ticks();
+        self.period_start += Duration::from_ticks(self.reset_period.as_ticks() * periods);
+        self.active_minutes = 0;
+    }
+}
+
+/// Start of the minute after `now`; motion before then would not change the count.
+pub fn next_minute(now: Instant) -> Instant {
+    Instant::from_secs((now.as_secs() / 60) * 60) + MINUTE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    #[test]
+    fn test_counts_minutes() {
+        let mut counter = ActivityCounter::new(DAY);
+        assert_eq!(counter.active_minutes(at(0)), 0);
+
+        // Any amount of motion in one minute is one active minute
+        counter.record_motion(at(60));
+        counter.record_motion(at(90));
+        counter.record_motion(at(119));
+        assert_eq!(counter.active_minutes(at(120)), 1);
+
+        counter.record_motion(at(120));
+        counter.record_motion(at(600));
+        assert_eq!(counter.active_minutes(at(600)), 3);
+    }
+
+    #[test]
+    fn test_rollover() {
+        let mut counter = ActivityCounter::new(DAY);
+        counter.record_motion(at(60));
+        counter.record_motion(at(120));
+        let end = DAY.as_secs();
+        assert_eq!(counter.active_minutes(at(end - 1)), 2);
+        assert_eq!(counter.active_minutes(at(end)), 0);
+
+        // Motion in the first minute of the new period counts towards it
+        counter.record_motion(at(end));
+        assert_eq!(counter.active_minutes(at(end + 30)), 1);
+    }
+
+    #[test]
+    fn test_rollover_after_long_sleep() {
+        let mut counter = ActivityCounter::new(DAY);
+        counter.record_motion(at(60));
+        // Slept through two and a half periods; the boundaries stay where they were
+        let day = DAY.as_secs();
+        counter.record_motion(at(2 * day + day / 2));
+        assert_eq!(counter.active_minutes(at(3 * day - 1)), 1);
+        assert_eq!(counter.active_minutes(at(3 * day)), 0);
+    }
+
+    #[test]
+    fn test_restore() {
+        let mut counter = ActivityCounter::new(DAY);
+        counter.restore(42);
+        counter.record_motion(at(60));
+        assert_eq!(counter.active_minutes(at(60)), 43);
+        assert_eq!(counter.active_minutes(at(DAY.as_secs())), 0);
+    }
+
+    #[test]
+    fn test_saturates() {
+        let mut counter = ActivityCounter::new(DAY);
+        counter.restore(u16::MAX);
+        counter.record_motion(at(60));
+        assert_eq!(counter.active_minutes(at(60)), u16::MAX);
+    }
+
+    #[test]
+    fn test_idle_for() {
+        let mut counter = ActivityCounter::new(DAY);
+        assert_eq!(counter.idle_for(at(300)), Duration::from_secs(300));
+        counter.record_motion(at(400));
+        assert_eq!(counter.idle_for(at(460)), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_next_minute() {
+        assert_eq!(next_minute(at(0)), at(60));
+        assert_eq!(next_minute(at(59)), at(60));
+        assert_eq!(next_minute(at(60)), at(120));
+    }
+}
@@ -0,0 +1,164 @@
+//! Advertising settings as types that can only hold values the softdevice and radio accept, so the
+//! limits live in one place instead of in comments next to raw numbers.
+//!
+//! The `const fn` constructors panic on a bad value, which fails the build when they're used for a
+//! `const`. The ones returning `Option` are for values from flash or the config window.
+//! [`AdvParams`] turns into `peripheral::Config` in one place: [`runtime`](crate::common::runtime).
+
+use embassy_time::Duration;
+
+use crate::common::scheduler::MAX_PHASE;
+
+/// Time between adverts, in the softdevice's 0.625ms units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct AdvInterval(u32);
+
+impl AdvInterval {
+    /// 20ms; the shortest the softdevice takes for non-connectable advertising
+    pub const MIN: Self = Self(32);
+    /// 10.24s; the softdevice rejects anything longer
+    pub const MAX: Self = Self(16384);
+
+    /// Rounded down to the nearest 0.625ms; panics if that's not between [`MIN`](Self::MIN) and
+    /// [`MAX`](Self::MAX).
+    pub const fn from_millis(ms: u32) -> Self {
+        // 1000 / 625
+        let units = ms as u64 * 8 / 5;
+        if units > Self::MAX.0 as u64 {
+            panic!("advertising interval is over 10.24s");
+        }
+        match Self::from_units(units as u32) {
+            Some(interval) => interval,
+            None => panic!("advertising interval is under 20ms"),
+        }
+    }
+
+    pub const fn from_units(units: u32) -> Option<Self> {
+        if units >= Self::MIN.0 && units <= Self::MAX.0 {
+            Some(Self(units))
+        } else {
+            None
+        }
+    }
+
+    /// 0.625ms units, for the softdevice
+    pub const fn units(self) -> u32 {
+        self.0
+    }
+
+    /// Rounded down
+    pub const fn as_millis(self) -> u32 {
+        self.0 * 5 / 8
+    }
+}
+
+/// How long an advertising window lasts; whole seconds, at least one and no more than
+/// [`MAX_PHASE`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct AdvWindow(u16);
+
+impl AdvWindow {
+    pub const MAX_SECS: u16 = MAX_PHASE.as_secs() as u16;
+
+    /// Panics if `secs` is 0 or over [`MAX_SECS`](Self::MAX_SECS).
+    pub const fn from_secs(secs: u16) -> Self {
+        match Self::try_from_secs(secs) {
+            Some(window) => window,
+            None => panic!("advertising window must be 1s to 30 minutes"),
+        }
+    }
+
+    pub const fn try_from_secs(secs: u16) -> Option<Self> {
+        if secs > 0 && secs <= Self::MAX_SECS {
+            Some(Self(secs))
+        } else {
+            None
+        }
+    }
+
+    pub const fn as_secs(self) -> u16 {
+        self.0
+    }
+
+    pub const fn duration(self) -> Duration {
+        Duration::from_secs(self.0 as u64)
+    }
+}
+
+/// Radio output power in dBm; only the levels the radio actually has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct TxPower(i8);
+
+impl TxPower {
+    /// Every level the radio has, lowest first; the 52832 goes up to +4dBm, the 52810 to 0
+    pub const LEVELS: &'static [i8] = if cfg!(feature = "nrf52832") {
+        &[-40, -20, -16, -12, -8, -4, 0, 3, 4]
+    } else {
+        &[-40, -20, -16, -12, -8, -4, 0]
+    };
+
+    /// Panics if the radio doesn't have `dbm`.
+    pub const fn from_dbm(dbm: i8) -> Self {
+        match Self::try_from_dbm(dbm) {
+            Some(power) => power,
+            None => panic!("not a TX power the radio has"),
+        }
+    }
+
+    pub const fn try_from_dbm(dbm: i8) -> Option<Self> {
+        let mut i = 0;
+        while i < Self::LEVELS.len() {
+            if Self::LEVELS[i] == dbm {
+                return Some(Self(dbm));
+            }
+            i += 1;
+        }
+        None
+    }
+
+    pub const fn dbm(self) -> i8 {
+        self.0
+    }
+}
+
+/// What goes into `peripheral::Config` for non-connectable advertising; everything else is left
+/// at the defaults.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct AdvParams {
+    pub interval: AdvInterval,
+    pub tx_power: TxPower,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval() {
+        assert_eq!(AdvInterval::from_millis(6000).units(), 9600);
+        assert_eq!(AdvInterval::from_millis(10_240), AdvInterval::MAX);
+        assert_eq!(AdvInterval::from_millis(20), AdvInterval::MIN);
+        assert_eq!(AdvInterval::from_millis(250).as_millis(), 250);
+        assert_eq!(AdvInterval::from_units(31), None);
+        assert_eq!(AdvInterval::from_units(16385), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_interval_too_long() {
+        AdvInterval::from_millis(u32::MAX);
+    }
+
+    #[test]
+    fn test_window() {
+        assert_eq!(AdvWindow::from_secs(10).duration(), Duration::from_secs(10));
+        assert_eq!(AdvWindow::try_from_secs(0), None);
+        assert_eq!(AdvWindow::try_from_secs(AdvWindow::MAX_SECS + 1), None);
+    }
+
+    #[test]
+    fn test_tx_power() {
+        assert_eq!(TxPower::from_dbm(-8).dbm(), -8);
+        assert_eq!(TxPower::try_from_dbm(-10), None);
+    }
+}
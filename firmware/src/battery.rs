@@ -0,0 +1,59 @@
+//! Battery voltage to the percentage that goes out in the BTHome battery object.
+//! The profile is picked at build time with `BTHP_BATTERY_PROFILE`; see build.rs.
+
+/// How the battery's voltage maps to how much is left in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum BatteryProfile {
+    /// Straight line across the chip's usable supply range, 1.7V to 3.6V.
+    /// Says nothing useful about a coin cell but doesn't assume anything about the battery either.
+    Linear,
+    /// Discharge curve of a CR2032 under a light load; from Nordic's `battery_level_in_percent()`.
+    /// Most of the capacity is between 2.9V and 2.7V.
+    Cr2032,
+}
+
+impl BatteryProfile {
+    pub fn percentage(self, millivolts: u16) -> u8 {
+        let mv = millivolts as i32;
+        let percentage = match self {
+            BatteryProfile::Linear => (mv - 1700) * 100 / (3600 - 1700),
+            BatteryProfile::Cr2032 => {
+                if mv >= 3000 {
+                    100
+                } else if mv > 2900 {
+                    100 - ((3000 - mv) * 58) / 100
+                } else if mv > 2740 {
+                    42 - ((2900 - mv) * 24) / 160
+                } else if mv > 2440 {
+                    18 - ((2740 - mv) * 12) / 300
+                } else if mv > 2100 {
+                    6 - ((2440 - mv) * 6) / 340
+                } else {
+                    0
+                }
+            }
+        };
+        percentage.clamp(0, 100) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear() {
+        assert_eq!(BatteryProfile::Linear.percentage(3600), 100);
+        assert_eq!(BatteryProfile::Linear.percentage(2650), 50);
+        assert_eq!(BatteryProfile::Linear.percentage(1700), 0);
+        assert_eq!(BatteryProfile::Linear.percentage(1000), 0);
+    }
+
+    #[test]
+    fn test_cr2032() {
+        assert_eq!(BatteryProfile::Cr2032.percentage(3200), 100);
+        assert_eq!(BatteryProfile::Cr2032.percentage(2900), 42);
+        assert_eq!(BatteryProfile::Cr2032.percentage(2740), 18);
+        assert_eq!(BatteryProfile::Cr2032.percentage(2000), 0);
+    }
+}
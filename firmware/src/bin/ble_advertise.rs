@@ -1,28 +1,26 @@
 #![no_std]
 #![no_main]
 
-//! A prototype; meant for the RAM constrained 810 tags.
-//! Does not use timers to duty cycle the advertisement as we don't have the RAM for it.
+//! A prototype from when the RAM constrained 810 tags couldn't run the main firmware.
+//! Always advertising, with made up battery data; `ble_advertise_timer` runs on the 810 now.
+//! Still handy as the smallest thing that shows up in Home Assistant.
 
 #[path = "../common.rs"]
 mod common;
 
+use common::advertising::{AdvInterval, AdvParams, TxPower};
+use common::config::{self, LfClock, LfClockSource};
+use common::runtime::Runtime;
+
 use defmt::{info, *};
 use embassy_executor::Spawner;
 use nrf_softdevice::ble::advertisement_builder::{
     AdvertisementDataType, ExtendedAdvertisementBuilder, ExtendedAdvertisementPayload, Flag,
 };
-use nrf_softdevice::ble::{peripheral, TxPower};
-
-use nrf_softdevice::{raw, Softdevice};
+use nrf_softdevice::ble::peripheral;
 
-// heapless str/vec for the pretend data
-use arrayvec::{ArrayString, ArrayVec};
-
-#[embassy_executor::task]
-async fn softdevice_task(sd: &'static Softdevice) -> ! {
-    sd.run().await
-}
+// heapless vec for the pretend data
+use arrayvec::ArrayVec;
 
 // Attempt to include the nrf softdevice binary in the final binary.
 const SOFTDEVICE_VAL: &[u8] = include_bytes!("../../nrf-soft-device/s112_nrf52_7.3.0.bin");
@@ -36,69 +34,30 @@ pub static SOFTDEVICE_BIN: [u8; SOFTDEVICE_VAL.len()] =
 async fn main(spawner: Spawner) {
     info!("Alive!");
 
-    let config = nrf_softdevice::Config {
+    let (rt, _board) = Runtime::builder()
         // TODO: figure out if this is the appropriate clock config?
         // There is an external oscillator...
-        clock: Some(raw::nrf_clock_lf_cfg_t {
-            source: raw::NRF_CLOCK_LF_SRC_RC as u8,
-            rc_ctiv: 16,
-            rc_temp_ctiv: 2,
-            accuracy: raw::NRF_CLOCK_LF_ACCURACY_500_PPM as u8,
-        }),
-
-        // We are expecting ZERO connections but the crate requires this be at least one
-        conn_gap: Some(raw::ble_gap_conn_cfg_t {
-            // We want 0 connections but setting this value to 0 produces a panic:
-            //  ERROR panicked at 'sd_ble_cfg_set 32 err InvalidParam'
-            conn_count: 1,
-
-            // Count of 1.25ms units set aside for connection
-            // This can be tweaked for more/less throughput?
-            // Changing this value doesn't appear to change the required RAM size.
-            event_length: 32,
-        }),
-
-        // We're trying to conserve as much ram as possible; we do not need a huge attribtes table as we're just broadcasting
-        gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t {
-            attr_tab_size: raw::BLE_GATTS_ATTR_TAB_SIZE_MIN,
-        }),
-
-        // Likewise, explicitly set the number of connections to 0
-        gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
-            adv_set_count: 0,
-            periph_role_count: 0,
-        }),
-
-        ..Default::default()
-    };
-    info!("Config made");
-
-    let sd = Softdevice::enable(&config);
-    info!("SoftDevice enabled...");
-    unwrap!(spawner.spawn(softdevice_task(sd)));
-    info!("SoftDevice running...");
+        .lf_clock(LfClock {
+            source: LfClockSource::Rc,
+            accuracy_ppm: 500,
+        })
+        // Changing this value doesn't appear to change the required RAM size.
+        .event_length(32)
+        .start(spawner);
 
     // See docs (//TODO: link) for the rationale behind these settings
     // There's a tradeoff between power consumption and responsiveness and range.
-    let mut config = peripheral::Config {
-        // How often the advertisement packet is sent out
-        // Unit is in 0.625ms increments. Default is 400 => 250ms
-
-        // 5 seconds is 8000 units of 0.625ms
-        // 30 seconds is 48000 units of 0.625ms
-        // TODO: make my own enum for this?
-        // Values too big will cause a panic
-        // sd_ble_gap_adv_set_configure err InvalidParam
-        // Absolute max is 16384, apparently.
-        // 16384 * 0.625ms = 10.24 seconds
-        interval: 16384,
+    let config: peripheral::Config = AdvParams {
+        // How often the advertisement packet is sent out; default is 250ms
+        // 10.24 seconds is as long as the softdevice goes
+        interval: AdvInterval::MAX,
 
         // Likewise, we can tune the power consumption
         // 0dBm is the default and results in a peak current draw of ~20ma
-        // Minus40dBm is the lowest power setting and results in a peak current draw of ~15ma
-        tx_power: TxPower::Minus4dBm,
-        ..Default::default()
-    };
+        // -40dBm is the lowest power setting and results in a peak current draw of ~15ma
+        tx_power: TxPower::from_dbm(-4),
+    }
+    .into();
 
     debug!("Config interval set to {}", config.interval);
 
@@ -147,23 +106,9 @@ async fn main(spawner: Spawner) {
            BT Home Pet Tracker  . I may come re-visit this later
     */
 
-    // Start with the MAC address
-    // Note that endianness is reversed in the MAC address
-    // If device address is e2:db:e8:62:67:0d, `mac` will be:
-    //      RandomStatic:[0d, 67, 62, e8, db, e2]
-    let mac_addr = nrf_softdevice::ble::get_address(sd).bytes();
-    //debug!("mac_addr bytes: {=[u8]:08b}", mac_addr);
-    debug!("mac_addr bytes: {=[u8]:02x}", mac_addr);
-
-    // // BTHPT_XXXX format is 10 chars
-    let mut device_name = ArrayString::<10>::from("BTHPT_").unwrap();
-
-    // Oh how I miss std and format!()
-    device_name.push(byte_to_hex(mac_addr[1])[0]);
-    device_name.push(byte_to_hex(mac_addr[1])[1]);
-    device_name.push(byte_to_hex(mac_addr[0])[0]);
-    device_name.push(byte_to_hex(mac_addr[0])[1]);
-
+    // BTHPT_XXXX where XXXX is the last two bytes of the MAC address, unless the BTHP_NAME_* build
+    // settings say otherwise
+    let device_name = rt.device_name(&config::Config::default().name_template());
     info!("Device name: {}", device_name.as_str());
 
     /*
@@ -231,26 +176,7 @@ async fn main(spawner: Spawner) {
         ADV_DATA.as_ref()
     );
 
-    let adv = peripheral::NonconnectableAdvertisement::NonscannableUndirected {
-        adv_data: &ADV_DATA,
-    };
     info!("Starting Advert task...");
     defmt::println!("{:?}", SOFTDEVICE_BIN.len());
-    unwrap!(peripheral::advertise(sd, adv, &config).await);
-}
-
-// /// Converts a single nibble (4 bits) to a hexadecimal character.
-fn nibble_to_hex_char(nibble: u8) -> char {
-    match nibble {
-        0x0..=0x9 => (b'0' + nibble) as char,
-        0xa..=0xf => (b'a' + nibble - 10) as char,
-        _ => '?', // Should never happen if used correctly
-    }
-}
-
-// /// Converts a byte to a two-character hexadecimal string.
-fn byte_to_hex(byte: u8) -> [char; 2] {
-    let high = (byte >> 4) & 0x0F;
-    let low = byte & 0x0F;
-    [nibble_to_hex_char(high), nibble_to_hex_char(low)]
+    rt.advertise(&ADV_DATA, &config).await;
 }
@@ -5,35 +5,91 @@
 //! It advertises battery level and presence information over BLE in BTHome format.
 //! There are quite a few opportunities for optimization and refactoring in this code!
 
+use core::cell::RefCell;
 use core::mem;
 
 #[path = "../common.rs"]
 mod common;
 
-use common::util::encoding::byte_to_hex;
+use common::activity::ActivityCounter;
+use common::advertising::{AdvInterval, AdvParams, TxPower};
+use common::board;
+use common::bthome::{Object, Payload};
+use common::build_config::{
+    ACTIVITY_RESET_HOURS, BATTERY_PROFILE, JITTER_PERCENT, SHELF_AFTER_MINS,
+};
+use common::config::{self, Channels, ConfigStore, Counters, DecodeError};
+use common::config_window::{self, Closed};
+use common::factory_reset;
+use common::power_fail;
+use common::reset_reason::LastReset;
+use common::runtime::Runtime;
+use common::scheduler::{DutyCycle, Jitter, Phase, Scheduler};
+use common::shelf::{self, WakeSources};
+use common::temperature::{self, SaadcCalibration};
+use common::uicr::Provisioning;
+use common::watchdog;
 
 use defmt::{info, *};
 use embassy_executor::Spawner;
-use embassy_nrf::interrupt::{self, InterruptExt, Priority};
+use embassy_futures::select::select;
+#[cfg(any(feature = "button", feature = "lis2dh12"))]
+use embassy_futures::select::Either;
+use embassy_nrf::gpio::Pin as _;
 use embassy_nrf::saadc::{ChannelConfig, Config, Resolution, Saadc, VddInput};
 use embassy_nrf::{bind_interrupts, saadc};
 
-use embassy_nrf::config::DcdcConfig;
-
-use embassy_time::{with_timeout, Duration, Timer};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+#[cfg(feature = "lis2dh12")]
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
 use nrf_softdevice::ble::advertisement_builder::{
-    AdvertisementDataType, ExtendedAdvertisementBuilder, ExtendedAdvertisementPayload, Flag,
+    AdvertisementDataType, Flag, LegacyAdvertisementBuilder, LegacyAdvertisementPayload,
 };
 
 use nrf_softdevice::ble::peripheral;
-use nrf_softdevice::{raw, Softdevice};
+use nrf_softdevice::Flash;
 
-use arrayvec::{ArrayString, ArrayVec};
+use arrayvec::ArrayVec;
 
 bind_interrupts!(struct Irqs {
     SAADC => saadc::InterruptHandler;
 });
 
+/// How often the active minutes count goes back to zero; `BTHP_ACTIVITY_RESET_HOURS`.
+/// Home Assistant keeps the history so the default daily reset makes for an easy "active minutes per day" graph.
+const ACTIVITY_RESET_PERIOD: Duration = Duration::from_secs(ACTIVITY_RESET_HOURS as u64 * 60 * 60);
+
+/// Anything above this on any axis counts as motion.
+/// Low enough to catch a dog walking around, high enough to ignore a dog breathing in its sleep.
+#[cfg(feature = "lis2dh12")]
+const MOTION_THRESHOLD_MG: u16 = 96;
+
+static ACTIVITY: Mutex<CriticalSectionRawMutex, RefCell<ActivityCounter>> =
+    Mutex::new(RefCell::new(ActivityCounter::new(ACTIVITY_RESET_PERIOD)));
+
+/// Whether the active profile wants the accelerometer; see [`update_accelerometer`].
+#[cfg(feature = "lis2dh12")]
+static ACCELEROMETER: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Room for the objects that take turns in the payload:
+/// the active profile, the activity count, illuminance plus whatever the I2C sensors report.
+const MAX_EXTRAS: usize = 3 + 2 * common::sensors::MAX_OBJECTS;
+
+/// How long, and how often, to say the battery is done for on a power failure warning.
+/// TODO: a guess at what a dying coin cell can still manage; not measured
+const POWER_FAIL_ADV_FOR: Duration = Duration::from_secs(2);
+const POWER_FAIL_ADV_INTERVAL: AdvInterval = AdvInterval::from_millis(250);
+
+/// Battery, plus the light sensor if there is one.
+const SAADC_CHANNELS: usize = if cfg!(feature = "photodiode") { 2 } else { 1 };
+
+/// Load resistor between the phototransistor's emitter and ground.
+/// TODO: 10k is a common value for these; measure the one on your board.
+#[cfg(feature = "photodiode")]
+const PHOTODIODE_LOAD_OHMS: u32 = 10_000;
+
 // Attempt to include the nrf softdevice binary in the final binary.
 // We are declaring twice because the static needs to have a size associated with it and
 // a const lets us get the size of the binary file at compile time.
@@ -46,42 +102,188 @@ const SOFTDEVICE_VAL: &[u8] = include_bytes!("../../nrf-soft-device/s112_nrf52_7
 pub static SOFTDEVICE_BIN: [u8; SOFTDEVICE_VAL.len()] =
     *include_bytes!("../../nrf-soft-device/s112_nrf52_7.3.0.bin");
 
+/// Counts active minutes from the accelerometer's motion interrupt.
+/// The I2C bus is only brought up to configure the accelerometer and to clear the latched
+/// interrupt; the rest of the time we're just waiting on a GPIO.
+/// While the active profile has it off (starting with `enabled`), the accelerometer is powered
+/// down until [`ACCELEROMETER`] says otherwise.
+#[cfg(feature = "lis2dh12")]
 #[embassy_executor::task]
-async fn softdevice_task(sd: &'static Softdevice) -> ! {
-    sd.run().await
+async fn activity_task(int1: embassy_nrf::gpio::AnyPin, mut enabled: bool) {
+    use common::i2c;
+    use common::sensors::lis2dh12::{self, Lis2dh12};
+    use embassy_nrf::gpio::{Input, Pull};
+
+    let mut int1 = Input::new(int1, Pull::None);
+
+    loop {
+        {
+            let mut bus = i2c::BUS.lock().await;
+            let bus = unwrap!(bus.as_mut());
+            let mut accel = Lis2dh12::new(bus.twim(), lis2dh12::ADDRESS);
+            if !enabled {
+                info!("activity_task: accelerometer disabled in config");
+                if let Err(e) = accel.power_down().await {
+                    warn!("activity_task: unable to power down accelerometer: {}", e);
+                }
+            } else if let Err(e) = accel.enable_motion_interrupt(MOTION_THRESHOLD_MG).await {
+                error!("activity_task: unable to set up accelerometer: {}", e);
+                // Try again with the next profile change
+                enabled = false;
+            }
+        }
+
+        if enabled {
+            info!("activity_task: waiting for motion...");
+            let changed = select(count_motion(&mut int1), ACCELEROMETER.wait()).await;
+            if let Either::Second(e) = changed {
+                enabled = e;
+            }
+        } else {
+            enabled = ACCELEROMETER.wait().await;
+        }
+    }
 }
 
-async fn do_advert(sd: &'static Softdevice, advertisement_data: ExtendedAdvertisementPayload) {
+/// Records a motion event on every rising edge of `int1`, one per minute at most.
+#[cfg(feature = "lis2dh12")]
+async fn count_motion(int1: &mut embassy_nrf::gpio::Input<'_, embassy_nrf::gpio::AnyPin>) {
+    use common::activity::next_minute;
+    use common::i2c;
+    use common::sensors::lis2dh12::{self, Lis2dh12};
+
     loop {
-        let phy_config = peripheral::Config {
-            // Time to wait between advertising packets.
-            // SoftDevices does not like values higher than 16384 (10.24 seconds)
-            // For this particular application, power savings is way more important than
-            // responsiveness.
-            // Sending out advert every 6 seconds is fine; at least one of those is going to be picked up.
-            // 9600 *.625ms = 6 seconds
-            interval: 9600,
-
-            // Likewise, we can tune the power consumption
-            // 0dBm is the default and results in a peak current draw of ~20ma with pretty good range.
-            // Minus40dBm is the lowest power setting and results in a peak current draw of ~15ma
-            //  but a noticeable decrease in range.
-            // Eventually both interval and power level will be configurable via app.
-            ..Default::default()
-        };
-
-        let advert_payload = peripheral::NonconnectableAdvertisement::NonscannableUndirected {
-            adv_data: &advertisement_data,
-        };
-        info!("do_advert: advertising...");
-        info!(
-            "do_advert: advertisement_data({}): {=[u8]:02x}",
-            advertisement_data.len(),
-            advertisement_data.as_ref()
-        );
-        unwrap!(peripheral::advertise(sd, advert_payload, &phy_config).await);
-        // Should never get here...
-        info!("do_advert: stop advertising...");
+        int1.wait_for_high().await;
+        let now = Instant::now();
+        ACTIVITY.lock(|a| a.borrow_mut().record_motion(now));
+        debug!("activity_task: motion!");
+
+        // The interrupt is latched; leave it that way until this minute is over.
+        // Otherwise a busy dog would wake us up 10 times a second for a minute that has already been counted.
+        Timer::at(next_minute(now)).await;
+
+        let mut bus = i2c::BUS.lock().await;
+        let bus = unwrap!(bus.as_mut());
+        if let Err(e) = Lis2dh12::new(bus.twim(), lis2dh12::ADDRESS)
+            .clear_interrupt()
+            .await
+        {
+            warn!("activity_task: unable to clear interrupt: {}", e);
+        }
+    }
+}
+
+/// Lets the activity task know whether the active profile in `app_config` wants the accelerometer.
+fn update_accelerometer(app_config: &config::Config) {
+    #[cfg(feature = "lis2dh12")]
+    ACCELEROMETER.signal(app_config.profile().sensors.accelerometer);
+    #[cfg(not(feature = "lis2dh12"))]
+    let _ = app_config;
+}
+
+/// Shelf mode; only comes back if there's nothing to wake up on.
+async fn shelve(wake: WakeSources) {
+    clear_motion(wake).await;
+    shelf::enter(wake);
+}
+
+/// Clears the accelerometer's latched interrupt before System OFF or it would wake us straight
+/// back up.
+async fn clear_motion(wake: WakeSources) {
+    #[cfg(feature = "lis2dh12")]
+    if wake.motion.is_some() {
+        use common::i2c;
+        use common::sensors::lis2dh12::{self, Lis2dh12};
+
+        let mut bus = i2c::BUS.lock().await;
+        let bus = unwrap!(bus.as_mut());
+        if let Err(e) = Lis2dh12::new(bus.twim(), lis2dh12::ADDRESS)
+            .clear_interrupt()
+            .await
+        {
+            warn!("shelf: unable to clear accelerometer interrupt: {}", e);
+        }
+    }
+    #[cfg(not(feature = "lis2dh12"))]
+    let _ = wake;
+}
+
+/// Saves the counters, says the battery is done for and goes to System OFF before the supply
+/// browns out. Without anything to wake up on, it stays off until the battery is swapped; staying
+/// on would only drain what's left of it.
+async fn power_failed(
+    rt: &Runtime,
+    store: &mut ConfigStore<Flash>,
+    packet_id: u8,
+    device_name: &str,
+    channels: Channels,
+    wake: WakeSources,
+) -> ! {
+    warn!("power fail: supply dropping; shutting down");
+    let counters = Counters {
+        packet_id,
+        active_minutes: ACTIVITY.lock(|a| a.borrow_mut().active_minutes(Instant::now())),
+    };
+    // Just one slot written; no time for an erase
+    match store.save_counters(&counters).await {
+        Ok(true) => info!("counters: saved {}", counters),
+        Ok(false) => warn!("counters: log full; not saved"),
+        Err(e) => warn!("counters: unable to save: {}", e),
+    }
+
+    let payload = unwrap!(Payload::build(
+        &[Object::PacketId(packet_id), Object::BatteryLow(true)],
+        &[],
+        &mut 0
+    ));
+    let advertisement_data: LegacyAdvertisementPayload = LegacyAdvertisementBuilder::new()
+        .flags(&[Flag::GeneralDiscovery, Flag::LE_Only])
+        .raw(AdvertisementDataType::SERVICE_DATA_16, payload.as_slice())
+        .adapt_name(device_name)
+        .build();
+    // Short and quick; a few adverts before the supply gives out
+    let phy_config: peripheral::Config = AdvParams {
+        interval: POWER_FAIL_ADV_INTERVAL,
+        tx_power: TxPower::from_dbm(0),
+    }
+    .into();
+    rt.advertise_until(
+        &advertisement_data,
+        phy_config,
+        channels,
+        Instant::now() + POWER_FAIL_ADV_FOR,
+    )
+    .await;
+    clear_motion(wake).await;
+    shelf::off(wake)
+}
+
+/// Samples whichever environmental sensors the board has and adds their readings to `extras`.
+/// A sensor that fails is logged and left out; there's always next time.
+#[cfg(any(feature = "sht3x", feature = "bmp280"))]
+async fn sample_environment(extras: &mut ArrayVec<Object, MAX_EXTRAS>) {
+    use common::i2c;
+    use common::sensors;
+
+    let mut bus = i2c::BUS.lock().await;
+    let bus = unwrap!(bus.as_mut());
+
+    #[cfg(feature = "sht3x")]
+    {
+        use common::sensors::sht3x::{self, Sht3x};
+        match sensors::sample(&mut Sht3x::new(bus.twim(), sht3x::ADDRESS)).await {
+            Ok(readings) => extras.extend(readings),
+            Err(e) => warn!("sht3x: {}", e),
+        }
+    }
+
+    #[cfg(feature = "bmp280")]
+    {
+        use common::sensors::bmp280::{self, Bmp280};
+        match sensors::sample(&mut Bmp280::new(bus.twim(), bmp280::ADDRESS)).await {
+            Ok(readings) => extras.extend(readings),
+            Err(e) => warn!("bmp280: {}", e),
+        }
     }
 }
 
@@ -90,64 +292,130 @@ async fn main(spawner: Spawner) {
     // Might be worth doing a bit more work in GHA to build a more informative version string with
     // the branch or tag name instead of just the short hash.
     info!("Main is alive! Build:{}", env!("CARGO_PKG_VERSION"));
-    let mut config = embassy_nrf::config::Config::default();
-
-    // Enable the DCDC converter for (slightly) lower power consumption
-    config.dcdc = DcdcConfig { reg1: true };
-
-    // Init required for timers but we need to adjust their priority to not break the softdevice
-    // Softdevice has reserved priorities 0, 1 and 4 and will cause a panic if we try to use them for other things
-    //      panicked at 'sd_softdevice_enable err SdmIncorrectInterruptConfiguration'
-    // See: https://github.com/embassy-rs/nrf-softdevice?tab=readme-ov-file#troubleshooting
-    // 0 is Highest. Lower priority number can preempt higher priority number
-
-    config.gpiote_interrupt_priority = Priority::P2;
-    config.time_interrupt_priority = Priority::P2;
-    interrupt::SAADC.set_priority(Priority::P3);
-
-    let mut p = embassy_nrf::init(config);
-    debug!("embassy_nrf::init: done!");
-
-    let sd_config = nrf_softdevice::Config {
-        clock: Some(raw::nrf_clock_lf_cfg_t {
-            source: raw::NRF_CLOCK_LF_SRC_XTAL as u8,
-            // ctiv values are only to be set to non-zero values when using an RC oscillator
-            // Both the HolyIoT and Duo tags use an external crystal
-            rc_ctiv: 0,
-            rc_temp_ctiv: 0,
-            // Default is 250 ppm; I don't have a datasheet for the crystal so I'm just going with the default?
-            accuracy: raw::NRF_CLOCK_LF_ACCURACY_250_PPM as u8,
-        }),
-
-        // We are expecting ZERO connections but the crate requires this be at least one
-        conn_gap: Some(raw::ble_gap_conn_cfg_t {
-            // We want 0 connections but setting this value to 0 produces a panic:
-            //  ERROR panicked at 'sd_ble_cfg_set 32 err InvalidParam'
-            conn_count: 1,
-
-            // Count of 1.25ms units set aside for connection
-            // This can be tweaked for more/less throughput?
-            event_length: 24,
-        }),
-
-        // We're trying to conserve as much ram as possible; we do not need a huge attributes table as we're just broadcasting
-        gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t {
-            attr_tab_size: raw::BLE_GATTS_ATTR_TAB_SIZE_MIN,
-        }),
-
-        gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
-            adv_set_count: 0,
-            periph_role_count: 0,
-        }),
-
-        ..Default::default()
+    // Has to be read before the softdevice takes over POWER
+    let last_reset = LastReset::take();
+    info!("last reset: {}", last_reset.describe().as_str());
+    // NVMC is ours until the softdevice starts; this is the only chance to clear the request
+    let mut reset_requested = factory_reset::requested_by_uicr();
+    // The softdevice needs to know about the LF clock before it (and its flash API) can start
+    // so this one bit of the config has to come straight out of flash.
+    let lf_clock = match config::read_mapped() {
+        Ok(c) if !reset_requested => c.lf_clock,
+        _ => config::Config::default().lf_clock,
+    };
+    debug!("lf_clock: {}", lf_clock);
+
+    let (rt, mut board, server) = Runtime::builder()
+        .lf_clock(lf_clock)
+        // The config window's GATT service needs more than the minimum.
+        // TODO: 1024 is a guess with some headroom; tune it down
+        .connectable(1024)
+        .start_with(spawner, |sd| unwrap!(config_window::Server::new(sd)));
+    let sd = rt.sd;
+    // From here on, a hang resets the tag; see also the `panic-reset` feature
+    watchdog::start(spawner, board.wdt);
+    power_fail::enable();
+    server.set_last_reset(&last_reset);
+
+    // Per-device values from UICR take the place of the build-time defaults
+    let provisioning = match Provisioning::read() {
+        Ok(p) => p,
+        Err(DecodeError::Blank) => Provisioning::default(),
+        Err(e) => {
+            warn!("uicr: provisioning record rejected: {}", e);
+            Provisioning::default()
+        }
+    };
+    info!(
+        "provisioning: bind key: {}, battery profile: {}",
+        provisioning.bind_key.is_some(),
+        provisioning.battery_profile
+    );
+    let battery_profile = provisioning.battery_profile.unwrap_or(BATTERY_PROFILE);
+
+    let mut config_store = ConfigStore::new(Flash::take(sd), config::offset())
+        .with_defaults(provisioning.apply(config::Config::default()));
+    let mut app_config = match config_store.load().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("config: unable to read flash: {}", e);
+            config_store.defaults()
+        }
+    };
+    info!("config: {}", app_config);
+
+    #[cfg(feature = "button")]
+    if let Some(pin) = board.button.as_mut() {
+        reset_requested |= factory_reset::requested_by_button(pin).await;
+    }
+    // Goes out in place of the usual readings with the first round of adverts
+    let mut announcement = Some(Object::Text(last_reset.cause.as_str()));
+    if reset_requested {
+        app_config = factory_reset::run(&mut config_store).await;
+        announcement = Some(factory_reset::ANNOUNCEMENT);
+    }
+    // Left behind if the last boot ended in a power failure
+    let counters = match config_store.load_counters().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("config: unable to read counters: {}", e);
+            None
+        }
+    };
+    if let Some(c) = counters {
+        info!("counters: carrying on from {}", c);
+        ACTIVITY.lock(|a| a.borrow_mut().restore(c.active_minutes));
+        // Restored once; a later watchdog reset or battery swap starts from zero like any other
+        if !power_fail::flash_ok() {
+            warn!("config: supply too low to clear the counters");
+        } else if let Err(e) = config_store.clear_counters().await {
+            warn!("config: unable to clear counters: {}", e);
+        }
+    }
+
+    // Sensors and accelerometer share the bus
+    #[cfg(feature = "i2c")]
+    {
+        // Boards without a bus don't build with the I2C features, see board::HAS_I2C
+        let pins = unwrap!(board.i2c, "{} has no I2C bus", board::NAME);
+        common::i2c::init(board.twim0, pins.sda, pins.scl).await;
+    }
+    // Pins that can wake the tag from shelf mode; the button does even without the `button` feature
+    let button_pin = board.button.as_ref().map(|pin| pin.pin());
+    #[cfg(feature = "lis2dh12")]
+    let motion_pin = board.accel_int1.as_ref().map(|pin| pin.pin());
+    #[cfg(not(feature = "lis2dh12"))]
+    let motion_pin: Option<u8> = None;
+    // Motion only wakes the tag if the accelerometer is on
+    let wake_sources = |app_config: &config::Config| WakeSources {
+        button: button_pin,
+        motion: motion_pin.filter(|_| app_config.profile().sensors.accelerometer),
     };
-    debug!("sd_config: made!");
 
-    let sd = Softdevice::enable(&sd_config);
-    debug!("Softdevice: enabled...");
-    unwrap!(spawner.spawn(softdevice_task(sd)));
-    debug!("Softdevice: running...");
+    #[cfg(feature = "lis2dh12")]
+    let has_accelerometer = match board.accel_int1 {
+        Some(int1) => {
+            unwrap!(spawner.spawn(activity_task(
+                int1,
+                app_config.profile().sensors.accelerometer
+            )));
+            true
+        }
+        None => {
+            warn!("{} has no accelerometer", board::NAME);
+            false
+        }
+    };
+    #[cfg(not(feature = "lis2dh12"))]
+    let has_accelerometer = false;
+    #[cfg(feature = "button")]
+    match board.button {
+        Some(pin) => unwrap!(spawner.spawn(common::button::button_task(pin))),
+        None => warn!("{} has no button", board::NAME),
+    }
+    // Likewise board::HAS_PHOTODIODE
+    #[cfg(feature = "photodiode")]
+    let mut photodiode = unwrap!(board.photodiode, "{} has no light sensor", board::NAME);
 
     // TODO: what happens in HA when we omit the device name from some of the packets?
     // I suspect that the sudden absence of a name will not trigger a rename in the UI but
@@ -155,122 +423,270 @@ async fn main(spawner: Spawner) {
     // advert at the expense of the user not reliably seeing a device name when they use their
     // phone to scan for devices... which is not expected to happen often!
 
-    // Start with the MAC address
-    // Note that endianness is reversed in the MAC address
-    // If device address is e2:db:e8:62:67:0d, `mac` will be:
-    //      RandomStatic:[0d, 67, 62, e8, db, e2]
-    let mac_addr = nrf_softdevice::ble::get_address(sd).bytes();
-    debug!("mac_addr bytes: {=[u8]:02x}", mac_addr);
-
-    // BTHPT_XXXX format is 10 chars
-    let mut device_name = ArrayString::<10>::from("BTHPT_").unwrap();
-
-    device_name.push(byte_to_hex(mac_addr[1])[0]);
-    device_name.push(byte_to_hex(mac_addr[1])[1]);
-    device_name.push(byte_to_hex(mac_addr[0])[0]);
-    device_name.push(byte_to_hex(mac_addr[0])[1]);
+    let mut device_name = rt.apply_identity(&app_config);
     info!("Device name: {}", device_name.as_str());
 
-    // Room for BT-Home payload
-    let mut bt_home_adv_data = ArrayVec::<u8, 16>::new();
-
-    // The shelly UUID + BT-Home version/flag byte
-    // TODO: probably make a small module with CONSTs for the values
-    for i in [0xd2, 0xfc, 0x40] {
-        bt_home_adv_data.push(i);
-    }
-
-    // Next push the packet ID into the payload
-    let mut packet_id = 0 as u8;
-    // TODO: create constants module so we have human readable names for types
-    // e.g. BT_HOME_DATA_TYPE_PACKET_ID = 0x00
-    bt_home_adv_data.push(0x00);
+    // Chance to change the settings, once the reset announcement has gone out; not after a reset
+    // nobody was there for
+    let mut open_window = config_window::opens_after(last_reset.cause);
 
-    // Longer term, I will probably need to re-factor this code to change _what_ is advertised
-    // each interval; there isn't enough room in _one_ advertisement payload to send
-    // battery and movement and packet ID and device_id/firmware_version ... etc.
+    // Goes out with every advert so receivers can tell a new reading from a repeated one.
     // See: https://bthome.io/format/#misc-data
-    // Right now we're just mentally keeping track that the 4th byte is the packet ID
-    // It might be worth creating a higher-level struct to wrap the underlying raw bytes and abstract this away a bit
-    bt_home_adv_data.push(packet_id as u8);
-
-    // Object ID 0x01 => Battery, 1 bytes
-    bt_home_adv_data.push(0x01);
-    // Push a bogus value now, will update once we actually poll ADC
-    bt_home_adv_data.push(0xff);
-
-    // Going to try also broadcasting a bool "presence" value to see if this allows me to ditch
-    // the manual / template automation that I _was_ using to link the RSSI to device_tracker / person.
-    // 0x25 => Presence, bool.
-    bt_home_adv_data.push(0x25);
-    // We hard-code "home" because any time the device is advertising, it's at home.
-    bt_home_adv_data.push(0x01);
+    let mut packet_id = counters.map_or(0, |c| c.packet_id.wrapping_add(1));
+    let mut saadc_calibration = SaadcCalibration::new();
+    let mut extras_cursor = 0;
+    // Built while measuring, sent while advertising
+    let mut bt_home_adv_data = Payload::new();
+    let mut scheduler = Scheduler::new().with_jitter(Jitter::new(JITTER_PERCENT, rt.random_seed()));
+    // Button event that cut the last sleep short
+    #[cfg(feature = "button")]
+    let mut woken_by: Option<common::bthome::ButtonEvent> = None;
 
     loop {
-        // New advertise interval starting up, push the correct packet_id
-        bt_home_adv_data.push(packet_id as u8);
-        bt_home_adv_data.swap_remove(4);
-
-        // TODO: this whole thing should be refactored into a separate function
-        // Following the pattern here: https://github.com/embassy-rs/embassy/blob/main/examples/nrf52840/src/bin/twim_lowpower.rs
-        // If I drop the ADC at the end of the loop / before sleep... will we have lower power usage. In testing, ~ 2uA less power usage!
-        let mut adc_config = Config::default();
-        adc_config.resolution = Resolution::_10BIT;
-
-        let channel_config = ChannelConfig::single_ended(VddInput);
-        let mut saadc = Saadc::new(&mut p.SAADC, Irqs, adc_config, [channel_config]);
-        saadc.calibrate().await;
-        debug!("adc: calibrated!");
-
-        // Read the battery
-        let mut buf = [0; 1];
-        saadc.sample(&mut buf).await;
-
-        // Drop the ADC to save (a tiny amount of) power
-        mem::drop(saadc);
-
-        // Turn the 10 bit value into a float
-        let sample = f32::from(buf[0]);
-        let voltage = (sample / 1024.0) * 3.6;
-
-        // Percentage of _usable_ voltage range
-        let percentage = ((voltage - 1.7) / (3.6 - 1.7)) * 100.0;
-        info!(
-            "sample: {} | voltage: {=f32:02} | percentage: {=f32:02}",
-            sample, voltage, percentage
+        if power_fail::is_failing() {
+            power_failed(
+                &rt,
+                &mut config_store,
+                packet_id,
+                &device_name,
+                app_config.profile().channels,
+                wake_sources(&app_config),
+            )
+            .await;
+        }
+        let phase = scheduler.phase();
+        // Each phase should be over about when it says; if it isn't, something is stuck
+        watchdog::expect_back_by(match phase {
+            Phase::Measure => Instant::now(),
+            Phase::Advertise { until } | Phase::Sleep { until } => until,
+        });
+        match phase {
+            Phase::Measure => {
+                // Die temperature is a decent proxy for what the battery is going through in a cold car / garage
+                let die_temp = match temperature::read_centi_celsius(sd) {
+                    Ok(t) => Some(t),
+                    Err(e) => {
+                        warn!("die temperature: unable to read: {}", e);
+                        None
+                    }
+                };
+                debug!("die temperature: {} centi-C", die_temp);
+
+                // TODO: this whole thing should be refactored into a separate function
+                // Following the pattern here: https://github.com/embassy-rs/embassy/blob/main/examples/nrf52840/src/bin/twim_lowpower.rs
+                // If I drop the ADC at the end of the loop / before sleep... will we have lower power usage. In testing, ~ 2uA less power usage!
+                let mut adc_config = Config::default();
+                adc_config.resolution = Resolution::_10BIT;
+
+                let channel_config = ChannelConfig::single_ended(VddInput);
+                #[cfg(not(feature = "photodiode"))]
+                let mut saadc = Saadc::new(&mut board.saadc, Irqs, adc_config, [channel_config]);
+                // Light sensor is sampled alongside the battery so it adds nothing to the idle current
+                #[cfg(feature = "photodiode")]
+                let mut saadc = Saadc::new(
+                    &mut board.saadc,
+                    Irqs,
+                    adc_config,
+                    [channel_config, ChannelConfig::single_ended(&mut photodiode)],
+                );
+                if saadc_calibration.is_due(die_temp) {
+                    saadc.calibrate().await;
+                    debug!("adc: calibrated!");
+                }
+
+                // Read the battery (and light sensor)
+                let mut buf = [0; SAADC_CHANNELS];
+                saadc.sample(&mut buf).await;
+
+                // Drop the ADC to save (a tiny amount of) power
+                mem::drop(saadc);
+
+                // 10 bit value across 0-3.6V
+                let millivolts = (buf[0].max(0) as u32 * 3600 / 1024) as u16;
+                let percentage = battery_profile.percentage(millivolts);
+                power_fail::set_battery_millivolts(millivolts);
+                info!(
+                    "sample: {} | millivolts: {} | percentage: {}",
+                    buf[0], millivolts, percentage
+                );
+
+                // Every advert gets these
+                let mut core = ArrayVec::<Object, 5>::new();
+                core.push(Object::PacketId(packet_id));
+                core.push(Object::Battery(percentage));
+                if let Some(t) = die_temp {
+                    core.push(Object::Temperature(t));
+                }
+                // Going to try also broadcasting a bool "presence" value to see if this allows me to ditch
+                // the manual / template automation that I _was_ using to link the RSSI to device_tracker / person.
+                // We hard-code "home" because any time the device is advertising, it's at home.
+                core.push(Object::Presence(true));
+                // Events have to go out as soon as possible; and only once
+                #[cfg(feature = "button")]
+                if let Some(event) = woken_by
+                    .take()
+                    .or_else(|| common::button::EVENTS.try_receive().ok())
+                {
+                    core.push(Object::Button(event));
+                }
+
+                // There isn't room for everything else in one advert; these take turns
+                let profile = *app_config.profile();
+                let mut extras = ArrayVec::<Object, MAX_EXTRAS>::new();
+                extras.push(Object::SmallCount(app_config.active_profile as u8));
+                // Without an accelerometer (or with it disabled) the count would always be 0; don't waste the bytes on it
+                if has_accelerometer && profile.sensors.accelerometer {
+                    let active_minutes =
+                        ACTIVITY.lock(|a| a.borrow_mut().active_minutes(Instant::now()));
+                    extras.push(Object::Count(active_minutes));
+                }
+                #[cfg(feature = "photodiode")]
+                {
+                    use common::sensors::photodiode;
+                    let centilux = photodiode::centilux(
+                        buf[1],
+                        PHOTODIODE_LOAD_OHMS,
+                        photodiode::ALS_PT19_NA_PER_LUX,
+                    );
+                    debug!("light: {} centilux", centilux);
+                    if profile.sensors.light {
+                        extras.push(Object::Illuminance(centilux));
+                    }
+                }
+                #[cfg(any(feature = "sht3x", feature = "bmp280"))]
+                if profile.sensors.environment {
+                    sample_environment(&mut extras).await;
+                }
+
+                // Right after a restart or a factory reset, one round of adverts says so instead of
+                // the usual readings
+                bt_home_adv_data = if let Some(announcement) = announcement.take() {
+                    let announcement = [Object::PacketId(packet_id), announcement];
+                    unwrap!(Payload::build(&announcement, &[], &mut 0))
+                } else {
+                    unwrap!(Payload::build(&core, &extras, &mut extras_cursor))
+                };
+
+                debug!(
+                    "bt_home_adv_data ({}) : {=[u8]:02x}",
+                    bt_home_adv_data.as_slice().len(),
+                    bt_home_adv_data.as_slice()
+                );
+            }
+            Phase::Advertise { until } => {
+                let profile = app_config.profile();
+                // Legacy adverts only; the extended payload would be 254 bytes of RAM for 31 used
+                let advertisement_data: LegacyAdvertisementPayload =
+                    LegacyAdvertisementBuilder::new()
+                        .flags(&[Flag::GeneralDiscovery, Flag::LE_Only])
+                        // Add the BT-Home data
+                        .raw(
+                            AdvertisementDataType::SERVICE_DATA_16,
+                            bt_home_adv_data.as_slice(),
+                        )
+                        .adapt_name(&device_name)
+                        .build();
+
+                // For this particular application, power savings is way more important than
+                // responsiveness.
+                // Sending out advert every 6 seconds is fine; at least one of those is going to be picked up.
+                // Likewise, we can tune the power consumption
+                // 0dBm is the default and results in a peak current draw of ~20ma with pretty good range.
+                // Minus40dBm is the lowest power setting and results in a peak current draw of ~15ma
+                //  but a noticeable decrease in range.
+                let phy_config: peripheral::Config = profile.adv_params().into();
+                // The softdevice stops on its own at the end of the window
+                rt.advertise_until(&advertisement_data, phy_config, profile.channels, until)
+                    .await;
+                debug!("advert time for {} elapsed", packet_id);
+                // Increment the packet ID
+                packet_id = packet_id.wrapping_add(1);
+            }
+            Phase::Sleep { until } => {
+                // Advertising should have stopped, attempt to enter a low power state
+                info!("Stopping advertising for a moment");
+                // A power failure warning ends the sleep; the top of the loop deals with it
+                let sleep = select(Timer::at(until), power_fail::wait());
+                #[cfg(not(feature = "button"))]
+                sleep.await;
+                // A button press shouldn't have to wait for the next advertising window
+                #[cfg(feature = "button")]
+                if let Either::Second(event) = select(sleep, common::button::EVENTS.receive()).await
+                {
+                    woken_by = Some(event);
+                }
+
+                #[cfg(feature = "button")]
+                if common::button::LONG_PRESSED.try_take().is_some() {
+                    info!("Long press; opening config window");
+                    open_window = true;
+                }
+
+                #[cfg(feature = "button")]
+                if common::button::HELD.try_take().is_some() {
+                    info!("Button held; going to shelf mode");
+                    shelve(wake_sources(&app_config)).await;
+                }
+
+                #[cfg(feature = "button")]
+                if common::button::DOUBLE_PRESSED.try_take().is_some() {
+                    app_config.active_profile = app_config.active_profile.next();
+                    info!(
+                        "Double press; switching to {} profile",
+                        app_config.active_profile
+                    );
+                    if !power_fail::flash_ok() {
+                        warn!("config: supply too low to save the profile");
+                    } else if let Err(e) = config_store.save(&app_config).await {
+                        warn!("config: unable to save: {}", e);
+                    }
+                    update_accelerometer(&app_config);
+                }
+
+                // Nobody has moved the tag in a while; it's probably in a box
+                let wake = wake_sources(&app_config);
+                let idle = ACTIVITY.lock(|a| a.borrow().idle_for(Instant::now()));
+                if SHELF_AFTER_MINS > 0
+                    && wake.motion.is_some()
+                    && idle >= Duration::from_secs(u64::from(SHELF_AFTER_MINS) * 60)
+                {
+                    info!(
+                        "No motion for {} minutes; going to shelf mode",
+                        idle.as_secs() / 60
+                    );
+                    shelve(wake).await;
+                }
+            }
+        }
+        // Not between measuring and advertising; whatever was measured (the reset announcement, at
+        // boot) goes out first
+        if open_window && !matches!(phase, Phase::Measure) {
+            open_window = false;
+            watchdog::expect_back_by(Instant::now() + config_window::WINDOW);
+            let closed = config_window::open(
+                sd,
+                &server,
+                &mut config_store,
+                &mut app_config,
+                &device_name,
+            )
+            .await;
+            if closed == Closed::FactoryReset {
+                app_config = factory_reset::run(&mut config_store).await;
+                ACTIVITY.lock(|a| *a.borrow_mut() = ActivityCounter::new(ACTIVITY_RESET_PERIOD));
+                announcement = Some(factory_reset::ANNOUNCEMENT);
+            }
+            device_name = rt.apply_identity(&app_config);
+            update_accelerometer(&app_config);
+            if closed == Closed::Shelf {
+                shelve(wake_sources(&app_config)).await;
+            }
+        }
+        let profile = app_config.profile();
+        scheduler.advance(
+            Instant::now(),
+            DutyCycle::new(profile.window, profile.off_secs),
         );
-
-        bt_home_adv_data.push(percentage as u8);
-        // Again, not super happy to have to mentally keep track that the 7th byte
-        // is where we store the battery; this should be re-factored into a struct :)
-        bt_home_adv_data.swap_remove(6);
-
-        debug!(
-            "bt_home_adv_data ({}) : {=[u8]:02x}",
-            bt_home_adv_data.len(),
-            bt_home_adv_data.as_slice()
-        );
-
-        let advertisement_data: ExtendedAdvertisementPayload = ExtendedAdvertisementBuilder::new()
-            .flags(&[Flag::GeneralDiscovery, Flag::LE_Only])
-            // Add the BT-Home data
-            .raw(AdvertisementDataType::SERVICE_DATA_16, &bt_home_adv_data)
-            .adapt_name(&device_name)
-            .build();
-
-        let res = with_timeout(Duration::from_secs(10), do_advert(sd, advertisement_data)).await;
-        // should result in Err(TimeoutError)
-        debug!("advert time for {} elapsed: {:?}", packet_id, res);
-        // Increment the packet ID
-        packet_id = packet_id.wrapping_add(1);
-
-        // Advertising should have stopped, attempt to enter a low power state
-        info!("Stopping advertising for a moment");
-        Timer::after(Duration::from_secs(10)).await;
     }
-    // TODO: use WDT to recover from panics?
-    // Yes, a lot of work went into not panicking but it would be nice to reboot
-    //  in the event that we do panic.
-
     // TODO: pull firmware version from cargo.toml / git tags and transmit that w/ BTHome data?
 }
@@ -0,0 +1,47 @@
+//! DUOWEISI nRF52832 tag with the LIS2DH12 accelerometer ("LS2DH" variant).
+//! See hardware/DUOWEISI for the datasheet and the pogo programmer.
+
+#[cfg(feature = "nrf52")]
+use embassy_nrf::gpio::Pin;
+#[cfg(feature = "nrf52")]
+use embassy_nrf::Peripherals;
+
+#[cfg(feature = "nrf52")]
+use super::{Board, I2cPins};
+use crate::common::config::{LfClock, LfClockSource};
+
+pub const NAME: &str = "DUOWEISI LS2DH";
+
+/// External crystal; I don't have a datasheet for it so I'm just going with the softdevice default
+pub const LF_CLOCK: LfClock = LfClock {
+    source: LfClockSource::Xtal,
+    accuracy_ppm: 250,
+};
+
+/// The power measurements in the readme were taken on this tag with the DC/DC converter on
+pub const DCDC: bool = true;
+
+/// Whether `take` hands out I2C pins; sensor features on a board without them don't build
+pub const HAS_I2C: bool = true;
+/// Whether `take` hands out a light sensor input; likewise for the `photodiode` feature
+pub const HAS_PHOTODIODE: bool = false;
+
+#[cfg(feature = "nrf52")]
+pub(super) fn take(p: Peripherals) -> Board {
+    Board {
+        saadc: p.SAADC,
+        twim0: p.TWISPI0,
+        wdt: p.WDT,
+        // TODO: confirm these against the board; the datasheet for this tag has been wrong before.
+        i2c: Some(I2cPins {
+            sda: p.P0_14.degrade(),
+            scl: p.P0_15.degrade(),
+        }),
+        accel_int1: Some(p.P0_16.degrade()),
+        // TODO: not traced yet
+        button: None,
+        // TODO: not traced yet
+        led: None,
+        photodiode: None,
+    }
+}
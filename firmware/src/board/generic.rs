@@ -0,0 +1,51 @@
+//! No particular board; what the firmware assumed before boards were a thing.
+//! Every optional peripheral is wired up so any combination of sensor features builds.
+//!
+//! TODO: the pins are unconfirmed guesses; check them against your board before relying on them.
+
+#[cfg(feature = "nrf52")]
+use embassy_nrf::gpio::Pin;
+#[cfg(feature = "nrf52")]
+use embassy_nrf::saadc::Input;
+#[cfg(feature = "nrf52")]
+use embassy_nrf::Peripherals;
+
+#[cfg(feature = "nrf52")]
+use super::{Board, I2cPins};
+use crate::common::config::{LfClock, LfClockSource};
+
+pub const NAME: &str = "generic";
+
+/// I don't have a datasheet for the crystal so I'm just going with the softdevice default
+pub const LF_CLOCK: LfClock = LfClock {
+    source: LfClockSource::Xtal,
+    accuracy_ppm: 250,
+};
+
+pub const DCDC: bool = true;
+
+/// Whether `take` hands out I2C pins; sensor features on a board without them don't build
+pub const HAS_I2C: bool = true;
+/// Whether `take` hands out a light sensor input; likewise for the `photodiode` feature
+pub const HAS_PHOTODIODE: bool = true;
+
+#[cfg(feature = "nrf52")]
+pub(super) fn take(p: Peripherals) -> Board {
+    Board {
+        saadc: p.SAADC,
+        #[cfg(feature = "nrf52810")]
+        twim0: p.TWI0,
+        #[cfg(feature = "nrf52832")]
+        twim0: p.TWISPI0,
+        wdt: p.WDT,
+        i2c: Some(I2cPins {
+            sda: p.P0_14.degrade(),
+            scl: p.P0_15.degrade(),
+        }),
+        accel_int1: Some(p.P0_16.degrade()),
+        // Left out until a pin is confirmed; a wrong guess would also be a shelf mode wake source
+        button: None,
+        led: None,
+        photodiode: Some(p.P0_02.degrade_saadc()),
+    }
+}
@@ -0,0 +1,47 @@
+//! HolyIoT 21014 and 22040; nRF52810, no sensors. Nothing the firmware cares about is known to
+//! differ between them, so they share this file; put anything that turns out to behind the feature.
+//! See hardware/holy-iot/21014 for the datasheet and schematic, hardware/holy-iot/22040 for the
+//! pogo programmer.
+
+#[cfg(feature = "nrf52")]
+use embassy_nrf::Peripherals;
+
+#[cfg(feature = "nrf52")]
+use super::Board;
+use crate::common::config::{LfClock, LfClockSource};
+
+#[cfg(feature = "board-holyiot-21014")]
+pub const NAME: &str = "HolyIoT 21014";
+#[cfg(feature = "board-holyiot-22040")]
+pub const NAME: &str = "HolyIoT 22040";
+
+/// There may be an external crystal, but that isn't confirmed; until it is, stick with the RC
+/// oscillator these tags have always run on. It's the safe choice either way.
+pub const LF_CLOCK: LfClock = LfClock {
+    source: LfClockSource::Rc,
+    accuracy_ppm: 500,
+};
+
+/// TODO: confirm the inductor is fitted; the firmware has always turned the DC/DC converter on
+pub const DCDC: bool = true;
+
+/// Whether `take` hands out I2C pins; sensor features on a board without them don't build
+pub const HAS_I2C: bool = false;
+/// Whether `take` hands out a light sensor input; likewise for the `photodiode` feature
+pub const HAS_PHOTODIODE: bool = false;
+
+#[cfg(feature = "nrf52")]
+pub(super) fn take(p: Peripherals) -> Board {
+    Board {
+        saadc: p.SAADC,
+        twim0: p.TWI0,
+        wdt: p.WDT,
+        i2c: None,
+        accel_int1: None,
+        // TODO: not traced yet
+        button: None,
+        // TODO: not traced yet
+        led: None,
+        photodiode: None,
+    }
+}
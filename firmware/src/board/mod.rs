@@ -0,0 +1,210 @@
+//! What differs between the tags we run on: the chip, the LF clock, whether the inductor for the
+//! DC/DC converter is fitted and which pins go where.
+//!
+//! Pick one with a `board-*` feature; each one also turns on its chip (and sensors).
+//! Without one you get the generic board, which is what the firmware assumed before boards were a thing.
+
+#[cfg(feature = "nrf52")]
+pub use self::chip::*;
+
+#[cfg(any(
+    all(feature = "board-holyiot-21014", feature = "board-holyiot-22040"),
+    all(feature = "board-holyiot-21014", feature = "board-duoweisi-ls2dh"),
+    all(feature = "board-holyiot-22040", feature = "board-duoweisi-ls2dh"),
+))]
+compile_error!("Only one board-* feature can be enabled at a time");
+
+#[cfg(feature = "board-duoweisi-ls2dh")]
+mod duoweisi_ls2dh;
+#[cfg(feature = "board-duoweisi-ls2dh")]
+use duoweisi_ls2dh as selected;
+
+#[cfg(not(any(
+    feature = "board-holyiot-21014",
+    feature = "board-holyiot-22040",
+    feature = "board-duoweisi-ls2dh"
+)))]
+mod generic;
+#[cfg(not(any(
+    feature = "board-holyiot-21014",
+    feature = "board-holyiot-22040",
+    feature = "board-duoweisi-ls2dh"
+)))]
+use generic as selected;
+
+#[cfg(any(feature = "board-holyiot-21014", feature = "board-holyiot-22040"))]
+mod holyiot;
+#[cfg(any(feature = "board-holyiot-21014", feature = "board-holyiot-22040"))]
+use holyiot as selected;
+
+/// Whether the DC/DC converter's inductor is fitted
+pub use selected::DCDC;
+/// Default LF clock settings; the config store can override them
+pub use selected::LF_CLOCK;
+/// Which board this firmware was built for; goes out in the logs
+pub use selected::NAME;
+
+// A sensor the board can't talk to would only show up as a panic at boot, and with `panic-reset`
+// that's a reset loop
+#[cfg(feature = "i2c")]
+const _: () = assert!(
+    selected::HAS_I2C,
+    "this board has no I2C bus; turn off the I2C sensor features (lis2dh12, sht3x, bmp280)"
+);
+#[cfg(feature = "photodiode")]
+const _: () = assert!(
+    selected::HAS_PHOTODIODE,
+    "this board has no light sensor; turn off the photodiode feature"
+);
+
+/// The part that needs the chip; the rest is just constants, which the host tests need too.
+#[cfg(feature = "nrf52")]
+mod chip {
+    use defmt::info;
+    use embassy_nrf::config::DcdcConfig;
+    use embassy_nrf::gpio::AnyPin;
+    use embassy_nrf::interrupt::{self, InterruptExt, Priority};
+    use embassy_nrf::peripherals::{SAADC, WDT};
+    use embassy_nrf::saadc::AnyInput;
+    use nrf_softdevice::raw;
+
+    use super::{selected, DCDC, NAME};
+    use crate::common::config::{LfClock, LfClockSource};
+
+    // The TWIM and its interrupt are named after whatever shares their address. On the 52832
+    // that's SPIM0 too; the 52810 has a separate SPI block. Everything else uses these names.
+    /// The TWIM the I2C bus runs on
+    #[cfg(feature = "nrf52810")]
+    pub use embassy_nrf::peripherals::TWI0 as Twim0;
+    /// The TWIM the I2C bus runs on
+    #[cfg(feature = "nrf52832")]
+    pub use embassy_nrf::peripherals::TWISPI0 as Twim0;
+
+    #[cfg(all(feature = "i2c", feature = "nrf52810"))]
+    embassy_nrf::bind_interrupts!(pub struct Twim0Irqs {
+        TWIM0_TWIS0_TWI0 => embassy_nrf::twim::InterruptHandler<Twim0>;
+    });
+    #[cfg(all(feature = "i2c", feature = "nrf52832"))]
+    embassy_nrf::bind_interrupts!(pub struct Twim0Irqs {
+        SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0 => embassy_nrf::twim::InterruptHandler<Twim0>;
+    });
+
+    pub struct I2cPins {
+        pub sda: AnyPin,
+        pub scl: AnyPin,
+    }
+
+    /// The peripherals and pins the application gets to use.
+    /// Anything a board doesn't have is `None`.
+    pub struct Board {
+        pub saadc: SAADC,
+        pub twim0: Twim0,
+        pub wdt: WDT,
+        pub i2c: Option<I2cPins>,
+        /// LIS2DH12 INT1
+        pub accel_int1: Option<AnyPin>,
+        /// Wired between the pin and ground
+        pub button: Option<AnyPin>,
+        /// Status LED; nothing drives it yet
+        pub led: Option<AnyPin>,
+        /// Analog light sensor
+        pub photodiode: Option<AnyInput>,
+    }
+
+    /// Brings up embassy for this board and hands out its peripherals, along with the softdevice
+    /// config to enable it with. `lf_clock` is usually [`LF_CLOCK`](super::LF_CLOCK), unless the
+    /// config store says otherwise; `attr_tab_size` is `None` for advertising only.
+    pub fn init(
+        lf_clock: &LfClock,
+        event_length: u16,
+        attr_tab_size: Option<u32>,
+    ) -> (Board, nrf_softdevice::Config) {
+        let mut config = embassy_nrf::config::Config::default();
+
+        // Slightly lower power consumption, but only with the inductor fitted; without it the chip
+        // can't run off of the DC/DC converter at all.
+        config.dcdc = DcdcConfig { reg1: DCDC };
+
+        // Init required for timers but we need to adjust their priority to not break the softdevice
+        // Softdevice has reserved priorities 0, 1 and 4 and will cause a panic if we try to use them for other things
+        //      panicked at 'sd_softdevice_enable err SdmIncorrectInterruptConfiguration'
+        // See: https://github.com/embassy-rs/nrf-softdevice?tab=readme-ov-file#troubleshooting
+        // 0 is Highest. Lower priority number can preempt higher priority number
+        config.gpiote_interrupt_priority = Priority::P2;
+        config.time_interrupt_priority = Priority::P2;
+        interrupt::SAADC.set_priority(Priority::P3);
+        #[cfg(feature = "nrf52810")]
+        interrupt::TWIM0_TWIS0_TWI0.set_priority(Priority::P3);
+        #[cfg(feature = "nrf52832")]
+        interrupt::SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0.set_priority(Priority::P3);
+
+        let p = embassy_nrf::init(config);
+        info!("board: {}", NAME);
+        // Has to be before the softdevice is enabled
+        crate::common::temperature::load_linearisation();
+
+        let sd_config = nrf_softdevice::Config {
+            clock: Some(lf_clock_cfg(lf_clock)),
+
+            // We are expecting ZERO connections when advertising only but the crate requires this be at least one
+            conn_gap: Some(raw::ble_gap_conn_cfg_t {
+                // We want 0 connections but setting this value to 0 produces a panic:
+                //  ERROR panicked at 'sd_ble_cfg_set 32 err InvalidParam'
+                conn_count: 1,
+
+                // This can be tweaked for more/less throughput?
+                // Changing this value doesn't appear to change the required RAM size.
+                event_length,
+            }),
+
+            // We're trying to conserve as much ram as possible; we do not need a huge attributes table if we're just broadcasting
+            gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t {
+                attr_tab_size: attr_tab_size.unwrap_or(raw::BLE_GATTS_ATTR_TAB_SIZE_MIN),
+            }),
+
+            // Likewise, no peripheral connections unless asked for
+            gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
+                adv_set_count: 0,
+                periph_role_count: attr_tab_size.is_some() as u8,
+            }),
+
+            ..Default::default()
+        };
+
+        (selected::take(p), sd_config)
+    }
+
+    /// Softdevice clock config from ours.
+    fn lf_clock_cfg(lf_clock: &LfClock) -> raw::nrf_clock_lf_cfg_t {
+        let (source, rc_ctiv, rc_temp_ctiv) = match lf_clock.source {
+            // Recalibrate every 4 seconds, and only if the temperature changed by more than 0.5C
+            // Same values as the Nordic examples
+            LfClockSource::Rc => (raw::NRF_CLOCK_LF_SRC_RC, 16, 2),
+            // ctiv values are only to be set to non-zero values when using an RC oscillator
+            LfClockSource::Xtal => (raw::NRF_CLOCK_LF_SRC_XTAL, 0, 0),
+            LfClockSource::Synth => (raw::NRF_CLOCK_LF_SRC_SYNTH, 0, 0),
+        };
+        // Round up to the closest accuracy the softdevice knows about; claiming a better clock
+        // than we have would make it open the receive window too late.
+        let accuracy = match lf_clock.accuracy_ppm {
+            0..=1 => raw::NRF_CLOCK_LF_ACCURACY_1_PPM,
+            2 => raw::NRF_CLOCK_LF_ACCURACY_2_PPM,
+            3..=5 => raw::NRF_CLOCK_LF_ACCURACY_5_PPM,
+            6..=10 => raw::NRF_CLOCK_LF_ACCURACY_10_PPM,
+            11..=20 => raw::NRF_CLOCK_LF_ACCURACY_20_PPM,
+            21..=30 => raw::NRF_CLOCK_LF_ACCURACY_30_PPM,
+            31..=50 => raw::NRF_CLOCK_LF_ACCURACY_50_PPM,
+            51..=75 => raw::NRF_CLOCK_LF_ACCURACY_75_PPM,
+            76..=100 => raw::NRF_CLOCK_LF_ACCURACY_100_PPM,
+            101..=150 => raw::NRF_CLOCK_LF_ACCURACY_150_PPM,
+            151..=250 => raw::NRF_CLOCK_LF_ACCURACY_250_PPM,
+            _ => raw::NRF_CLOCK_LF_ACCURACY_500_PPM,
+        };
+        raw::nrf_clock_lf_cfg_t {
+            source: source as u8,
+            rc_ctiv,
+            rc_temp_ctiv,
+            accuracy: accuracy as u8,
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Just enough of the BTHome v2 format to build the service data payload.
+//! See: https://bthome.io/format/ and the [notes](../docs/bthome-notes/notes.md)
+
+use arrayvec::ArrayVec;
+
+/// The 16 bit UUID assigned to BTHome (0xFCD2); little endian as it goes out over the air.
+pub const SERVICE_UUID: [u8; 2] = [0xd2, 0xfc];
+
+/// BTHome device information byte.
+///     bit 0: 0 = no encryption
+///     bit 2: 0 = device is sending regular data updates
+///     bit 5-7: 010 = BTHome version 2
+pub const DEVICE_INFO: u8 = 0x40;
+
+/// Room for the service data (UUID + device info + objects) in a legacy advertisement.
+/// Flags take 3 of the 31 bytes and the service data record header takes 2 more.
+/// Whatever is left after this is used for the (possibly shortened) device name.
+pub const MAX_PAYLOAD_LEN: usize = 16;
+
+/// UUID + device info byte
+const HEADER_LEN: usize = SERVICE_UUID.len() + 1;
+
+/// The smallest object is 2 bytes (ID + value)
+const MAX_OBJECTS: usize = (MAX_PAYLOAD_LEN - HEADER_LEN) / 2;
+
+/// Object IDs, named as they are in the BTHome docs.
+pub mod id {
+    pub const PACKET_ID: u8 = 0x00;
+    pub const BATTERY: u8 = 0x01;
+    pub const TEMPERATURE: u8 = 0x02;
+    pub const HUMIDITY: u8 = 0x03;
+    pub const PRESSURE: u8 = 0x04;
+    pub const ILLUMINANCE: u8 = 0x05;
+    pub const COUNT_U8: u8 = 0x09;
+    pub const BATTERY_LOW: u8 = 0x15;
+    pub const PRESENCE: u8 = 0x25;
+    pub const BUTTON: u8 = 0x3a;
+    pub const COUNT_U16: u8 = 0x3d;
+    pub const TEXT: u8 = 0x53;
+}
+
+/// Values for the button event object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum ButtonEvent {
+    Press = 0x01,
+    DoublePress = 0x02,
+    LongPress = 0x04,
+}
+
+/// A single BTHome object and its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Object {
+    PacketId(u8),
+    /// Percentage, 0-100
+    Battery(u8),
+    /// sint16, 0.01°C
+    Temperature(i16),
+    /// uint16, 0.01%
+    Humidity(u16),
+    /// uint24, 0.01 hPa
+    Pressure(u32),
+    /// uint24, 0.01 lux
+    Illuminance(u32),
+    Presence(bool),
+    /// Binary sensor; true is low
+    BatteryLow(bool),
+    /// Event; only send it once per press
+    Button(ButtonEvent),
+    /// Generic counter; uint16
+    Count(u16),
+    /// Generic counter; uint8
+    SmallCount(u8),
+    /// UTF-8; keep it short, it's 2 bytes plus the text
+    Text(&'static str),
+}
+
+impl Object {
+    pub const fn id(&self) -> u8 {
+        match self {
+            Object::PacketId(_) => id::PACKET_ID,
+            Object::Battery(_) => id::BATTERY,
+            Object::Temperature(_) => id::TEMPERATURE,
+            Object::Humidity(_) => id::HUMIDITY,
+            Object::Pressure(_) => id::PRESSURE,
+            Object::Illuminance(_) => id::ILLUMINANCE,
+            Object::Presence(_) => id::PRESENCE,
+            Object::BatteryLow(_) => id::BATTERY_LOW,
+            Object::Button(_) => id::BUTTON,
+            Object::Count(_) => id::COUNT_U16,
+            Object::SmallCount(_) => id::COUNT_U8,
+            Object::Text(_) => id::TEXT,
+        }
+    }
+
+    /// Number of bytes this object takes up in the payload, including the object ID.
+    pub const fn encoded_len(&self) -> usize {
+        1 + match self {
+            Object::PacketId(_)
+            | Object::Battery(_)
+            | Object::Presence(_)
+            | Object::BatteryLow(_)
+            | Object::Button(_)
+            | Object::SmallCount(_) => 1,
+            Object::Temperature(_) | Object::Humidity(_) | Object::Count(_) => 2,
+            Object::Pressure(_) | Object::Illuminance(_) => 3,
+            // Length byte, then the text
+            Object::Text(s) => 1 + s.len(),
+        }
+    }
+
+    fn encode(&self, out: &mut ArrayVec<u8, MAX_PAYLOAD_LEN>) {
+        out.push(self.id());
+        match *self {
+            Object::PacketId(v) | Object::Battery(v) | Object::SmallCount(v) => out.push(v),
+            Object::Temperature(v) => out.extend(v.to_le_bytes()),
+            Object::Humidity(v) => out.extend(v.to_le_bytes()),
+            Object::Pressure(v) | Object::Illuminance(v) => {
+                out.extend(v.to_le_bytes()[..3].iter().copied())
+            }
+            Object::Presence(v) | Object::BatteryLow(v) => out.push(v as u8),
+            Object::Button(v) => out.push(v as u8),
+            Object::Count(v) => out.extend(v.to_le_bytes()),
+            Object::Text(s) => {
+                out.push(s.len() as u8);
+                out.extend(s.bytes());
+            }
+        }
+    }
+}
+
+/// Returned when an object does not fit in what is left of the payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct PayloadFull;
+
+/// BTHome service data, ready to be handed to the advertisement builder.
+pub struct Payload {
+    buf: ArrayVec<u8, MAX_PAYLOAD_LEN>,
+}
+
+impl Payload {
+    pub fn new() -> Self {
+        let mut buf = ArrayVec::new();
+        buf.extend(SERVICE_UUID);
+        buf.push(DEVICE_INFO);
+        Self { buf }
+    }
+
+    /// Appends an object to the payload.
+    /// BTHome wants objects in ascending object ID order; it is up to the caller to push them that way.
+    pub fn push(&mut self, object: Object) -> Result<(), PayloadFull> {
+        if self.buf.remaining_capacity() < object.encoded_len() {
+            return Err(PayloadFull);
+        }
+        object.encode(&mut self.buf);
+        Ok(())
+    }
+
+    /// Builds a payload with every one of `core` plus as many of `extras` as will fit.
+    /// There isn't room for everything in one advert so `extras` take turns; picking starts at `cursor`
+    /// and `cursor` is moved past whatever was picked so the next payload starts with the ones left out.
+    /// Objects are sorted into ascending object ID order.
+    pub fn build(
+        core: &[Object],
+        extras: &[Object],
+        cursor: &mut usize,
+    ) -> Result<Self, PayloadFull> {
+        let mut objects = ArrayVec::<Object, MAX_OBJECTS>::new();
+        let mut room = MAX_PAYLOAD_LEN - HEADER_LEN;
+        for object in core {
+            room = room.checked_sub(object.encoded_len()).ok_or(PayloadFull)?;
+            objects.try_push(*object).map_err(|_| PayloadFull)?;
+        }
+
+        if !extras.is_empty() {
+            let start = *cursor % extras.len();
+            let mut picked = 0;
+            for object in extras.iter().cycle().skip(start).take(extras.len()) {
+                if object.encoded_len() > room || objects.is_full() {
+                    break;
+                }
+                room -= object.encoded_len();
+                objects.push(*object);
+                picked += 1;
+            }
+            // Something too big to ever fit would otherwise hold up everything behind it
+            *cursor = (start + picked.max(1)) % extras.len();
+        }
+
+        objects.sort_unstable_by_key(Object::id);
+        let mut payload = Self::new();
+        for object in objects {
+            payload.push(object)?;
+        }
+        Ok(payload)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.buf.as_slice()
+    }
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push() {
+        let mut payload = Payload::new();
+        payload.push(Object::PacketId(7)).unwrap();
+        payload.push(Object::Temperature(-1050)).unwrap();
+        payload.push(Object::Pressure(100653)).unwrap();
+        assert_eq!(
+            payload.as_slice(),
+            &[0xd2, 0xfc, 0x40, 0x00, 0x07, 0x02, 0xe6, 0xfb, 0x04, 0x2d, 0x89, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_push_text() {
+        let mut payload = Payload::new();
+        payload.push(Object::PacketId(1)).unwrap();
+        payload.push(Object::Text("reset")).unwrap();
+        assert_eq!(
+            payload.as_slice(),
+            &[0xd2, 0xfc, 0x40, 0x00, 0x01, 0x53, 0x05, b'r', b'e', b's', b'e', b't']
+        );
+        // 12 bytes used, 4 left
+        assert_eq!(payload.push(Object::Text("abc")), Err(PayloadFull));
+    }
+
+    #[test]
+    fn test_push_small_count() {
+        let mut payload = Payload::new();
+        payload.push(Object::SmallCount(2)).unwrap();
+        assert_eq!(payload.as_slice(), &[0xd2, 0xfc, 0x40, 0x09, 0x02]);
+    }
+
+    #[test]
+    fn test_push_battery_low() {
+        let mut payload = Payload::new();
+        payload.push(Object::BatteryLow(true)).unwrap();
+        assert_eq!(payload.as_slice(), &[0xd2, 0xfc, 0x40, 0x15, 0x01]);
+    }
+
+    #[test]
+    fn test_push_full() {
+        let mut payload = Payload::new();
+        for _ in 0..4 {
+            payload.push(Object::Count(1)).unwrap();
+        }
+        // 3 + 4 * 3 = 15 bytes used; not even a 2 byte object fits in what's left
+        assert_eq!(payload.push(Object::Count(1)), Err(PayloadFull));
+        assert_eq!(payload.push(Object::Battery(1)), Err(PayloadFull));
+        assert_eq!(payload.as_slice().len(), 15);
+    }
+
+    #[test]
+    fn test_build_rotates_extras() {
+        let core = [
+            Object::PacketId(1),
+            Object::Presence(true),
+            Object::Battery(50),
+        ];
+        // 3 + 6 bytes of core leaves room for two of these at a time
+        let extras = [
+            Object::Count(1),
+            Object::Humidity(2),
+            Object::Temperature(3),
+        ];
+        let mut cursor = 0;
+
+        let payload = Payload::build(&core, &extras, &mut cursor).unwrap();
+        assert_eq!(
+            payload.as_slice(),
+            &[
+                0xd2, 0xfc, 0x40, 0x00, 0x01, 0x01, 0x32, 0x03, 0x02, 0x00, 0x25, 0x01, 0x3d, 0x01,
+                0x00
+            ]
+        );
+        assert_eq!(cursor, 2);
+
+        let payload = Payload::build(&core, &extras, &mut cursor).unwrap();
+        assert_eq!(
+            payload.as_slice(),
+            &[
+                0xd2, 0xfc, 0x40, 0x00, 0x01, 0x01, 0x32, 0x02, 0x03, 0x00, 0x25, 0x01, 0x3d, 0x01,
+                0x00
+            ]
+        );
+        assert_eq!(cursor, 1);
+    }
+
+    #[test]
+    fn test_build_core_too_big() {
+        let core = [Object::Count(1); 5];
+        assert!(Payload::build(&core, &[], &mut 0).is_err());
+    }
+}
@@ -0,0 +1,9 @@
+//! Settings baked in at build time from the `BTHP_*` environment variables; see build.rs and the readme.
+//! These are the defaults for [`Config`](crate::common::config::Config); anything saved in flash wins.
+
+use crate::common::advertising::{AdvInterval, AdvWindow, TxPower};
+use crate::common::battery::BatteryProfile;
+use crate::common::config::Channels;
+use crate::common::util::encoding::{HexCase, NameSource};
+
+include!(concat!(env!("OUT_DIR"), "/build_config.rs"));
@@ -0,0 +1,229 @@
+//! Debounced button on GPIOTE.
+//! Presses are classified as press / double press / long press and queued up for the main loop
+//! to send as BTHome button events.
+//! Long and double presses, and holding the button down for [`HOLD`], are also signalled on their
+//! own so local actions can hang off of them. A hold goes out over the air as a long press.
+//! Nothing counts until the button has been up once; see [`wait_for_release`].
+
+use core::convert::Infallible;
+
+#[cfg(feature = "nrf52")]
+use defmt::{debug, warn};
+#[cfg(feature = "nrf52")]
+use embassy_nrf::gpio::{AnyPin, Input, Pull};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
+#[cfg(feature = "nrf52")]
+use embassy_time::{Instant, Timer};
+use embedded_hal_async::digital::Wait;
+
+use crate::common::bthome::ButtonEvent;
+
+/// The button pads on these tags are just bare contacts and they bounce quite a bit; anything
+/// shorter is ignored
+const DEBOUNCE: Duration = Duration::from_millis(20);
+/// Held at least this long is a long press
+const LONG_PRESS: Duration = Duration::from_millis(1000);
+/// Held at least this long is a hold rather than a long press
+pub const HOLD: Duration = Duration::from_secs(5);
+/// A second press has to start within this long after the first one is released to be a double press
+#[cfg(feature = "nrf52")]
+const DOUBLE_PRESS_GAP: Duration = Duration::from_millis(300);
+
+/// Events waiting to go out over the air.
+/// If nobody picks them up, the oldest are kept and newer ones are dropped.
+pub static EVENTS: Channel<CriticalSectionRawMutex, ButtonEvent, 4> = Channel::new();
+
+/// Fires on every long press; for local actions that aren't just reporting the press.
+pub static LONG_PRESSED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Fires on every double press; for local actions that aren't just reporting the press.
+pub static DOUBLE_PRESSED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Fires as soon as the button has been down for [`HOLD`]; instead of [`LONG_PRESSED`], not as well.
+pub static HELD: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// What a press was, going by how long the button was down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+enum Length {
+    /// Too short to be a press; the contacts bouncing or a glitch
+    Bounce,
+    Short,
+    Long,
+    /// Down for [`HOLD`] or more
+    Hold,
+}
+
+impl Length {
+    fn of(down_for: Duration) -> Self {
+        if down_for < DEBOUNCE {
+            Self::Bounce
+        } else if down_for < LONG_PRESS {
+            Self::Short
+        } else if down_for < HOLD {
+            Self::Long
+        } else {
+            Self::Hold
+        }
+    }
+}
+
+/// Returns once the button (high when up) has been up for [`DEBOUNCE`].
+/// The button can still be down when the task starts: held through a boot-time factory reset, or
+/// since it woke the tag from shelf mode. Taking that for a new press would turn a hold that went
+/// on a little too long into a long press (config window) or another hold (shelf mode).
+async fn wait_for_release<B: Wait<Error = Infallible>>(button: &mut B) {
+    loop {
+        let Ok(()) = button.wait_for_high().await;
+        // Back down within the debounce time is the contacts bouncing
+        if with_timeout(DEBOUNCE, button.wait_for_low()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Button is wired between the pin and ground.
+#[cfg(feature = "nrf52")]
+#[embassy_executor::task]
+pub async fn button_task(pin: AnyPin) {
+    let mut button = Input::new(pin, Pull::Up);
+    wait_for_release(&mut button).await;
+
+    loop {
+        button.wait_for_low().await;
+        let pressed_at = Instant::now();
+        // A hold is acted on as soon as it is one, without waiting for the release
+        let down_for = match with_timeout(HOLD, button.wait_for_high()).await {
+            Ok(()) => pressed_at.elapsed(),
+            Err(_) => HOLD,
+        };
+
+        let event = match Length::of(down_for) {
+            // The contacts bounce low and high a few times before they settle either way
+            Length::Bounce => continue,
+            Length::Short => {
+                // Let the release settle before looking for a second press
+                Timer::after(DEBOUNCE).await;
+                match with_timeout(DOUBLE_PRESS_GAP, button.wait_for_low()).await {
+                    Ok(()) => {
+                        button.wait_for_high().await;
+                        DOUBLE_PRESSED.signal(());
+                        ButtonEvent::DoublePress
+                    }
+                    Err(_) => ButtonEvent::Press,
+                }
+            }
+            Length::Long => {
+                LONG_PRESSED.signal(());
+                ButtonEvent::LongPress
+            }
+            Length::Hold => {
+                HELD.signal(());
+                button.wait_for_high().await;
+                ButtonEvent::LongPress
+            }
+        };
+        Timer::after(DEBOUNCE).await;
+
+        debug!("button: {}", event);
+        if EVENTS.try_send(event).is_err() {
+            warn!("button: event queue full, dropping {}", event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::future::{poll_fn, Future};
+    use core::pin::{pin, Pin};
+    use core::task::{Context, Poll, Waker};
+    use embassy_time::MockDriver;
+    use embedded_hal::digital::ErrorType;
+
+    /// A button pad whose level the test sets; `true` is up
+    struct Pad<'a>(&'a Cell<bool>);
+
+    impl Pad<'_> {
+        async fn wait_for(&self, up: bool) -> Result<(), Infallible> {
+            poll_fn(|_| {
+                if self.0.get() == up {
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+
+    impl ErrorType for Pad<'_> {
+        type Error = Infallible;
+    }
+
+    impl Wait for Pad<'_> {
+        async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+            self.wait_for(true).await
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+            self.wait_for(false).await
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+            unimplemented!()
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+            unimplemented!()
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+            unimplemented!()
+        }
+    }
+
+    /// Polls once; the test moves the clock and the pad between polls instead of relying on wakers
+    fn poll<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn test_length() {
+        let ms = Duration::from_millis;
+        assert_eq!(Length::of(ms(0)), Length::Bounce);
+        assert_eq!(Length::of(DEBOUNCE - ms(1)), Length::Bounce);
+        assert_eq!(Length::of(DEBOUNCE), Length::Short);
+        assert_eq!(Length::of(LONG_PRESS - ms(1)), Length::Short);
+        assert_eq!(Length::of(LONG_PRESS), Length::Long);
+        assert_eq!(Length::of(HOLD - ms(1)), Length::Long);
+        assert_eq!(Length::of(HOLD), Length::Hold);
+        assert_eq!(Length::of(HOLD * 10), Length::Hold);
+    }
+
+    #[test]
+    fn test_wait_for_release() {
+        // Still down from a factory reset hold; however long that goes on, it's not a press
+        let up = Cell::new(false);
+        let mut pad = Pad(&up);
+        let mut release = pin!(wait_for_release(&mut pad));
+        assert!(poll(release.as_mut()).is_pending());
+        MockDriver::get().advance(HOLD * 2);
+        assert!(poll(release.as_mut()).is_pending());
+
+        // Let go, bouncing on the way
+        up.set(true);
+        assert!(poll(release.as_mut()).is_pending());
+        up.set(false);
+        assert!(poll(release.as_mut()).is_pending());
+        up.set(true);
+        assert!(poll(release.as_mut()).is_pending());
+
+        // Up for long enough
+        MockDriver::get().advance(DEBOUNCE);
+        assert!(poll(release.as_mut()).is_ready());
+    }
+}
@@ -1,7 +1,45 @@
+//! Code shared by the binaries. Anything that needs the chip is behind the `nrf52` feature so the
+//! rest builds on the host for its tests; see lib.rs.
+
 #![macro_use]
 
+#[cfg(all(feature = "nrf52", not(feature = "production")))]
 use defmt_rtt as _; // global logger
+#[cfg(feature = "nrf52")]
 use embassy_nrf as _; // time driver
+#[cfg(all(feature = "nrf52", not(feature = "panic-reset")))]
 use panic_probe as _;
 
+pub mod activity;
+pub mod advertising;
+pub mod battery;
+pub mod board;
+pub mod bthome;
+pub mod build_config;
+#[cfg(any(feature = "button", test))]
+pub mod button;
+pub mod config;
+#[cfg(feature = "nrf52")]
+pub mod config_window;
+#[cfg(feature = "nrf52")]
+pub mod factory_reset;
+#[cfg(feature = "i2c")]
+pub mod i2c;
+#[cfg(any(feature = "production", test))]
+mod null_logger;
+#[cfg(feature = "panic-reset")]
+mod panic_reset;
+#[cfg(feature = "nrf52")]
+pub mod power_fail;
+pub mod reset_reason;
+#[cfg(feature = "nrf52")]
+pub mod runtime;
+pub mod scheduler;
+pub mod sensors;
+#[cfg(feature = "nrf52")]
+pub mod shelf;
+pub mod temperature;
+pub mod uicr;
 pub mod util;
+#[cfg(feature = "nrf52")]
+pub mod watchdog;
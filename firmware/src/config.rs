@@ -0,0 +1,969 @@
+//! Persistent configuration, kept in a reserved page of internal flash (`CONFIG` in memory-*.x).
+//!
+//! The page holds a single record:
+//!
+//! ```text
+//! magic (2) | schema version (1) | body length (1) | body (n) | crc16 (2)
+//! ```
+//!
+//! The CRC covers everything before it. A blank page, bad CRC or unknown version all fall back to defaults.
+//! When the layout of [`Config`] changes, bump [`SCHEMA_VERSION`] and teach [`decode_body`] how to turn the
+//! previous version's body into the new `Config`; tags in the field keep their settings across the update.
+//!
+//! Further into the page is a log of [`Counters`], one 8 byte slot per save:
+//!
+//! ```text
+//! marker (1) | packet id (1) | active minutes (2) | reserved (2) | crc16 (2)
+//! ```
+//!
+//! Saving them only ever writes the next erased slot, never erases, so it's quick enough to do
+//! when the power is about to go. The newest slot that checks out wins; a torn one is skipped.
+//! They're only for carrying on after a power failure, so the log is cleared once they've been
+//! restored.
+
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::common::advertising::{AdvInterval, AdvParams, AdvWindow, TxPower};
+use crate::common::board;
+use crate::common::build_config;
+use crate::common::util::crc::crc16;
+use crate::common::util::encoding::NameTemplate;
+
+pub const SCHEMA_VERSION: u8 = 2;
+
+const MAGIC: [u8; 2] = *b"BP";
+const HEADER_LEN: usize = 4;
+const CRC_LEN: usize = 2;
+/// Big enough for any version of the record, with room to grow
+pub const MAX_RECORD_LEN: usize = 64;
+
+/// Where the counters log starts, from the start of the page; well clear of the config record
+const COUNTERS_AT: u32 = 1024;
+const COUNTERS_SLOT_LEN: usize = 8;
+const COUNTERS_MARKER: u8 = 0xc0;
+
+/// Longest name prefix we'll store; the rest of the name is the hex suffix.
+pub const NAME_PREFIX_LEN: usize = 8;
+
+/// Low frequency clock source; same numbering as `NRF_CLOCK_LF_SRC_*`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum LfClockSource {
+    Rc = 0,
+    Xtal = 1,
+    Synth = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LfClock {
+    pub source: LfClockSource,
+    pub accuracy_ppm: u16,
+}
+
+/// Which sensors a profile reads; ones the board doesn't have are skipped either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Sensors {
+    /// Accelerometer for active minutes
+    pub accelerometer: bool,
+    /// SHT3x / BMP280 on the I2C bus
+    pub environment: bool,
+    /// Photodiode
+    pub light: bool,
+}
+
+impl Sensors {
+    pub const ALL: Sensors = Sensors {
+        accelerometer: true,
+        environment: true,
+        light: true,
+    };
+    pub const NONE: Sensors = Sensors {
+        accelerometer: false,
+        environment: false,
+        light: false,
+    };
+
+    fn to_bits(self) -> u8 {
+        self.accelerometer as u8 | (self.environment as u8) << 1 | (self.light as u8) << 2
+    }
+
+    fn from_bits(bits: u8) -> Result<Self, DecodeError> {
+        if bits & !0b111 != 0 {
+            return Err(DecodeError::BadValue);
+        }
+        Ok(Sensors {
+            accelerometer: bits & 0b001 != 0,
+            environment: bits & 0b010 != 0,
+            light: bits & 0b100 != 0,
+        })
+    }
+}
+
+/// Which of the three primary advertising channels a profile uses; at least one.
+/// Fewer channels is less time with the radio on per advert, at the cost of a proxy having to be
+/// listening on one of the ones that are left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Channels {
+    pub ch37: bool,
+    pub ch38: bool,
+    pub ch39: bool,
+}
+
+impl Channels {
+    pub const ALL: Channels = Channels {
+        ch37: true,
+        ch38: true,
+        ch39: true,
+    };
+
+    /// Bit 0 is channel 37; set is used. Same as the config window characteristic.
+    pub fn to_bits(self) -> u8 {
+        self.ch37 as u8 | (self.ch38 as u8) << 1 | (self.ch39 as u8) << 2
+    }
+
+    pub fn from_bits(bits: u8) -> Result<Self, DecodeError> {
+        // No channels at all would mean never being heard
+        if bits & !0b111 != 0 || bits == 0 {
+            return Err(DecodeError::BadValue);
+        }
+        Ok(Channels {
+            ch37: bits & 0b001 != 0,
+            ch38: bits & 0b010 != 0,
+            ch39: bits & 0b100 != 0,
+        })
+    }
+
+    pub fn count(self) -> u8 {
+        self.to_bits().count_ones() as u8
+    }
+}
+
+/// How the tag behaves; one per [`ProfileId`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Profile {
+    /// Time between advertising packets
+    pub adv_interval: AdvInterval,
+    pub tx_power: TxPower,
+    /// How long each advertising window lasts
+    pub window: AdvWindow,
+    /// How long to sleep between advertising windows; at least 1
+    pub off_secs: u16,
+    pub sensors: Sensors,
+    /// Primary advertising channels
+    pub channels: Channels,
+}
+
+impl Profile {
+    const ENCODED_LEN: usize = 11;
+
+    fn encode(&self, out: &mut [u8]) {
+        out[0..4].copy_from_slice(&self.adv_interval.units().to_le_bytes());
+        out[4] = self.tx_power.dbm() as u8;
+        out[5..7].copy_from_slice(&self.window.as_secs().to_le_bytes());
+        out[7..9].copy_from_slice(&self.off_secs.to_le_bytes());
+        out[9] = self.sensors.to_bits();
+        out[10] = self.channels.to_bits();
+    }
+
+    fn decode(body: &[u8]) -> Result<Self, DecodeError> {
+        let adv_interval = u32::from_le_bytes([body[0], body[1], body[2], body[3]]);
+        let window = u16::from_le_bytes([body[5], body[6]]);
+        // No sleep at all would have the scheduler spinning
+        let off_secs = match u16::from_le_bytes([body[7], body[8]]) {
+            0 => return Err(DecodeError::BadValue),
+            off_secs => off_secs,
+        };
+        Ok(Profile {
+            adv_interval: AdvInterval::from_units(adv_interval).ok_or(DecodeError::BadValue)?,
+            tx_power: TxPower::try_from_dbm(body[4] as i8).ok_or(DecodeError::BadValue)?,
+            window: AdvWindow::try_from_secs(window).ok_or(DecodeError::BadValue)?,
+            off_secs,
+            sensors: Sensors::from_bits(body[9])?,
+            channels: Channels::from_bits(body[10])?,
+        })
+    }
+
+    pub fn adv_params(&self) -> AdvParams {
+        AdvParams {
+            interval: self.adv_interval,
+            tx_power: self.tx_power,
+        }
+    }
+}
+
+/// Named profiles; the number is what goes out over the air.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum ProfileId {
+    /// Whatever the firmware was built with
+    Home = 0,
+    /// Away from the receivers at home; nobody is listening for presence so advertise less
+    Travel = 1,
+    /// Sitting in a drawer; just enough to be found again
+    Storage = 2,
+}
+
+impl ProfileId {
+    pub const COUNT: usize = 3;
+
+    pub fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(ProfileId::Home),
+            1 => Some(ProfileId::Travel),
+            2 => Some(ProfileId::Storage),
+            _ => None,
+        }
+    }
+
+    /// For cycling through them with the button
+    pub fn next(self) -> Self {
+        match self {
+            ProfileId::Home => ProfileId::Travel,
+            ProfileId::Travel => ProfileId::Storage,
+            ProfileId::Storage => ProfileId::Home,
+        }
+    }
+}
+
+/// Built-in profiles; Home is the build-time settings.
+/// TODO: Travel and Storage are starting points, not measured; tune them with the ppk2
+const fn default_profiles() -> [Profile; ProfileId::COUNT] {
+    let home = Profile {
+        adv_interval: build_config::ADV_INTERVAL,
+        tx_power: build_config::TX_POWER,
+        window: build_config::ADV_WINDOW,
+        off_secs: build_config::OFF_SECS,
+        sensors: Sensors::ALL,
+        channels: build_config::ADV_CHANNELS,
+    };
+    let travel = Profile {
+        off_secs: 60,
+        sensors: Sensors {
+            accelerometer: true,
+            environment: false,
+            light: false,
+        },
+        ..home
+    };
+    let storage = Profile {
+        adv_interval: AdvInterval::MAX,
+        tx_power: TxPower::from_dbm(-8),
+        window: AdvWindow::from_secs(10),
+        off_secs: 600,
+        sensors: Sensors::NONE,
+        channels: Channels::ALL,
+    };
+    [home, travel, storage]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Config {
+    /// ASCII, padded with 0
+    pub name_prefix: [u8; NAME_PREFIX_LEN],
+    pub lf_clock: LfClock,
+    /// Static random address to use instead of the chip's own, least significant byte first.
+    /// Lets a replacement tag take over the identity of the one it replaces.
+    pub static_address: Option<[u8; 6]>,
+    /// Which of `profiles` is in use
+    pub active_profile: ProfileId,
+    /// Indexed by [`ProfileId`]
+    pub profiles: [Profile; ProfileId::COUNT],
+}
+
+impl Default for Config {
+    /// Whatever the firmware was built with; see build.rs and the board module
+    fn default() -> Self {
+        Self {
+            name_prefix: build_config::NAME_PREFIX,
+            lf_clock: board::LF_CLOCK,
+            static_address: None,
+            active_profile: ProfileId::Home,
+            profiles: default_profiles(),
+        }
+    }
+}
+
+impl Config {
+    /// The profile in use
+    pub fn profile(&self) -> &Profile {
+        &self.profiles[self.active_profile as usize]
+    }
+
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        &mut self.profiles[self.active_profile as usize]
+    }
+
+    /// The name prefix as a string; falls back to the default if what's stored isn't valid.
+    pub fn name_prefix(&self) -> &str {
+        let len = self
+            .name_prefix
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(NAME_PREFIX_LEN);
+        core::str::from_utf8(&self.name_prefix[..len]).unwrap_or(build_config::NAME_PREFIX_STR)
+    }
+
+    /// The stored prefix with the suffix settings the firmware was built with.
+    pub fn name_template(&self) -> NameTemplate<'_> {
+        NameTemplate {
+            prefix: self.name_prefix(),
+            suffix_bytes: build_config::NAME_SUFFIX_BYTES,
+            source: build_config::NAME_SOURCE,
+            case: build_config::NAME_CASE,
+        }
+    }
+
+    /// Serializes the current schema version of the body.
+    fn encode_body(&self, out: &mut [u8]) -> usize {
+        out[0..8].copy_from_slice(&self.name_prefix);
+        out[8] = self.lf_clock.source as u8;
+        out[9..11].copy_from_slice(&self.lf_clock.accuracy_ppm.to_le_bytes());
+        out[11] = self.static_address.is_some() as u8;
+        out[12..18].copy_from_slice(&self.static_address.unwrap_or_default());
+        out[18] = self.active_profile as u8;
+        for (profile, out) in self
+            .profiles
+            .iter()
+            .zip(out[19..].chunks_exact_mut(Profile::ENCODED_LEN))
+        {
+            profile.encode(out);
+        }
+        19 + ProfileId::COUNT * Profile::ENCODED_LEN
+    }
+
+    /// Serializes the whole record, CRC included. Returns the number of bytes used in `out`.
+    pub fn encode(&self, out: &mut [u8; MAX_RECORD_LEN]) -> usize {
+        let body_len = self.encode_body(&mut out[HEADER_LEN..]);
+        out[0..2].copy_from_slice(&MAGIC);
+        out[2] = SCHEMA_VERSION;
+        out[3] = body_len as u8;
+        let crc_at = HEADER_LEN + body_len;
+        let crc = crc16(&out[..crc_at]);
+        out[crc_at..crc_at + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        crc_at + CRC_LEN
+    }
+
+    /// Parses a record, migrating it from an older schema version if needed.
+    pub fn decode(record: &[u8]) -> Result<Self, DecodeError> {
+        if record.len() < HEADER_LEN + CRC_LEN {
+            return Err(DecodeError::BadLength);
+        }
+        if record[..HEADER_LEN].iter().all(|&b| b == 0xff) {
+            return Err(DecodeError::Blank);
+        }
+        if record[0..2] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = record[2];
+        let body_len = record[3] as usize;
+        let crc_at = HEADER_LEN + body_len;
+        if record.len() < crc_at + CRC_LEN {
+            return Err(DecodeError::BadLength);
+        }
+        let crc = u16::from_le_bytes([record[crc_at], record[crc_at + 1]]);
+        if crc != crc16(&record[..crc_at]) {
+            return Err(DecodeError::BadCrc);
+        }
+        decode_body(version, &record[HEADER_LEN..crc_at])
+    }
+}
+
+/// One arm per schema version that has ever shipped.
+/// Older versions decode their own layout and fill in anything newer with defaults.
+fn decode_body(version: u8, body: &[u8]) -> Result<Config, DecodeError> {
+    match version {
+        1 => decode_v1(body),
+        2 => decode_v2(body),
+        v => Err(DecodeError::UnknownVersion(v)),
+    }
+}
+
+/// Shared settings up front, then the profiles; the v1 settings became the Home profile.
+fn decode_v2(body: &[u8]) -> Result<Config, DecodeError> {
+    if body.len() != 19 + ProfileId::COUNT * Profile::ENCODED_LEN {
+        return Err(DecodeError::BadLength);
+    }
+    let mut name_prefix = [0; NAME_PREFIX_LEN];
+    name_prefix.copy_from_slice(&body[0..8]);
+    let mut address = [0; 6];
+    address.copy_from_slice(&body[12..18]);
+    let mut profiles = default_profiles();
+    for (profile, bytes) in profiles
+        .iter_mut()
+        .zip(body[19..].chunks_exact(Profile::ENCODED_LEN))
+    {
+        *profile = Profile::decode(bytes)?;
+    }
+    Ok(Config {
+        name_prefix,
+        lf_clock: decode_lf_clock(&body[8..11])?,
+        static_address: decode_static_address(body[11], address)?,
+        active_profile: ProfileId::from_u8(body[18]).ok_or(DecodeError::BadValue)?,
+        profiles,
+    })
+}
+
+/// A single set of settings; they went into the Home profile, the rest is defaults.
+fn decode_v1(body: &[u8]) -> Result<Config, DecodeError> {
+    if body.len() != 20 {
+        return Err(DecodeError::BadLength);
+    }
+    let mut name_prefix = [0; NAME_PREFIX_LEN];
+    name_prefix.copy_from_slice(&body[9..17]);
+    // Same layout as a v2 profile, less the sensors and channels
+    let mut profile = [0; Profile::ENCODED_LEN];
+    profile[..9].copy_from_slice(&body[..9]);
+    profile[9] = Sensors::ALL.to_bits();
+    profile[10] = Channels::ALL.to_bits();
+    let mut profiles = default_profiles();
+    profiles[ProfileId::Home as usize] = Profile::decode(&profile)?;
+    Ok(Config {
+        name_prefix,
+        lf_clock: decode_lf_clock(&body[17..20])?,
+        profiles,
+        ..Default::default()
+    })
+}
+
+fn decode_lf_clock(bytes: &[u8]) -> Result<LfClock, DecodeError> {
+    let source = match bytes[0] {
+        0 => LfClockSource::Rc,
+        1 => LfClockSource::Xtal,
+        2 => LfClockSource::Synth,
+        _ => return Err(DecodeError::BadValue),
+    };
+    Ok(LfClock {
+        source,
+        accuracy_ppm: u16::from_le_bytes([bytes[1], bytes[2]]),
+    })
+}
+
+fn decode_static_address(present: u8, address: [u8; 6]) -> Result<Option<[u8; 6]>, DecodeError> {
+    match present {
+        0 => Ok(None),
+        1 if is_static_address(&address) => Ok(Some(address)),
+        _ => Err(DecodeError::BadValue),
+    }
+}
+
+/// Whether `address` (least significant byte first) is a valid static random address:
+/// top two bits set and the other 46 neither all 0 nor all 1.
+pub fn is_static_address(address: &[u8; 6]) -> bool {
+    let random = u64::from_le_bytes([
+        address[0], address[1], address[2], address[3], address[4], address[5], 0, 0,
+    ]);
+    let random_bits = random & ((1 << 46) - 1);
+    random >> 46 == 0b11 && random_bits != 0 && random_bits != (1 << 46) - 1
+}
+
+/// Running state worth keeping across a brown-out; see the module docs for how it's stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Counters {
+    /// The last one sent
+    pub packet_id: u8,
+    pub active_minutes: u16,
+}
+
+impl Counters {
+    fn encode(&self) -> [u8; COUNTERS_SLOT_LEN] {
+        let mut slot = [0xff; COUNTERS_SLOT_LEN];
+        slot[0] = COUNTERS_MARKER;
+        slot[1] = self.packet_id;
+        slot[2..4].copy_from_slice(&self.active_minutes.to_le_bytes());
+        let crc = crc16(&slot[..6]);
+        slot[6..8].copy_from_slice(&crc.to_le_bytes());
+        slot
+    }
+
+    fn decode(slot: &[u8; COUNTERS_SLOT_LEN]) -> Option<Self> {
+        if slot[0] != COUNTERS_MARKER || u16::from_le_bytes([slot[6], slot[7]]) != crc16(&slot[..6])
+        {
+            return None;
+        }
+        Some(Self {
+            packet_id: slot[1],
+            active_minutes: u16::from_le_bytes([slot[2], slot[3]]),
+        })
+    }
+}
+
+/// Why a record was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum DecodeError {
+    /// Erased flash; nothing has ever been saved
+    Blank,
+    BadMagic,
+    BadLength,
+    BadCrc,
+    /// A field holds a value that can't be right
+    BadValue,
+    /// Written by newer firmware than this one
+    UnknownVersion(u8),
+}
+
+extern "C" {
+    /// Start of the `CONFIG` region; see memory-*.x
+    static __config_start: u8;
+}
+
+/// Flash address of the reserved page.
+pub fn offset() -> u32 {
+    core::ptr::addr_of!(__config_start) as u32
+}
+
+/// Decodes the record straight out of memory mapped flash.
+/// Only meant for before the softdevice is enabled (it needs the LF clock settings);
+/// after that, go through [`ConfigStore`].
+pub fn read_mapped() -> Result<Config, DecodeError> {
+    let record = unsafe { core::slice::from_raw_parts(offset() as *const u8, MAX_RECORD_LEN) };
+    Config::decode(record)
+}
+
+/// Reads and writes the config record through any (async) NOR flash.
+/// On the tag that's `nrf_softdevice::Flash`, which is safe to use while the radio is running.
+pub struct ConfigStore<F> {
+    flash: F,
+    /// Start of the reserved page
+    offset: u32,
+    /// What to use when nothing usable is stored
+    defaults: Config,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    pub fn new(flash: F, offset: u32) -> Self {
+        Self {
+            flash,
+            offset,
+            defaults: Config::default(),
+        }
+    }
+
+    /// Something other than the build-time defaults to fall back to; e.g. with provisioned values.
+    pub fn with_defaults(mut self, defaults: Config) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    pub fn defaults(&self) -> Config {
+        self.defaults
+    }
+
+    /// Wipes the record; the next load gives the defaults.
+    pub async fn erase(&mut self) -> Result<(), F::Error> {
+        self.flash
+            .erase(self.offset, self.offset + F::ERASE_SIZE as u32)
+            .await
+    }
+
+    /// Loads the stored config; if there isn't a usable one, the defaults.
+    pub async fn load(&mut self) -> Result<Config, F::Error> {
+        let mut record = [0; MAX_RECORD_LEN];
+        self.flash.read(self.offset, &mut record).await?;
+        Ok(match Config::decode(&record) {
+            Ok(config) => config,
+            Err(e) => {
+                defmt::warn!("config: using defaults; stored config rejected: {}", e);
+                self.defaults
+            }
+        })
+    }
+
+    /// Replaces the stored config; the counters are kept.
+    pub async fn save(&mut self, config: &Config) -> Result<(), F::Error> {
+        let mut record = [0xff; MAX_RECORD_LEN];
+        let len = config.encode(&mut record);
+        // Writes have to be a multiple of the write size; the padding is left as erased flash
+        let len = len.next_multiple_of(F::WRITE_SIZE);
+        let (counters, _) = self.scan_counters().await?;
+        self.rewrite(&record[..len], counters).await
+    }
+
+    /// The newest counters saved, if any.
+    pub async fn load_counters(&mut self) -> Result<Option<Counters>, F::Error> {
+        Ok(self.scan_counters().await?.0)
+    }
+
+    /// Appends `counters` to the log without erasing anything.
+    /// Returns `false` if the log is full; [`clear_counters`](Self::clear_counters) makes room.
+    pub async fn save_counters(&mut self, counters: &Counters) -> Result<bool, F::Error> {
+        match self.scan_counters().await? {
+            (_, Some(free)) => {
+                self.flash.write(free, &counters.encode()).await?;
+                Ok(true)
+            }
+            (_, None) => Ok(false),
+        }
+    }
+
+    /// Empties the counters log. Erases the page, so only when there's power to spare.
+    pub async fn clear_counters(&mut self) -> Result<(), F::Error> {
+        // The config record goes back as it was, valid or not
+        let mut record = [0; MAX_RECORD_LEN];
+        self.flash.read(self.offset, &mut record).await?;
+        self.rewrite(&record, None).await
+    }
+
+    /// The newest valid counters and the first erased slot.
+    async fn scan_counters(&mut self) -> Result<(Option<Counters>, Option<u32>), F::Error> {
+        let mut newest = None;
+        let start = self.offset + COUNTERS_AT;
+        let end = self.offset + F::ERASE_SIZE as u32;
+        for at in (start..end).step_by(COUNTERS_SLOT_LEN) {
+            let mut slot = [0; COUNTERS_SLOT_LEN];
+            self.flash.read(at, &mut slot).await?;
+            if slot.iter().all(|&b| b == 0xff) {
+                return Ok((newest, Some(at)));
+            }
+            // A torn write doesn't check out; whatever came before it still stands
+            newest = Counters::decode(&slot).or(newest);
+        }
+        Ok((newest, None))
+    }
+
+    async fn rewrite(&mut self, record: &[u8], counters: Option<Counters>) -> Result<(), F::Error> {
+        self.erase().await?;
+        self.flash.write(self.offset, record).await?;
+        if let Some(counters) = counters {
+            self.flash
+                .write(self.offset + COUNTERS_AT, &counters.encode())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_storage_async::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    const PAGE: usize = 4096;
+
+    /// Two pages of in-memory "flash"; the config lives in the second one
+    struct MemFlash {
+        data: [u8; 2 * PAGE],
+    }
+
+    impl MemFlash {
+        fn new() -> Self {
+            Self {
+                data: [0xff; 2 * PAGE],
+            }
+        }
+    }
+
+    impl ErrorType for MemFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MemFlash {
+        const READ_SIZE: usize = 1;
+
+        async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for MemFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = PAGE;
+
+        async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.data[from as usize..to as usize].fill(0xff);
+            Ok(())
+        }
+
+        async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            if !(offset as usize).is_multiple_of(Self::WRITE_SIZE)
+                || !bytes.len().is_multiple_of(Self::WRITE_SIZE)
+            {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            // NOR flash can only clear bits
+            for (d, b) in self.data[offset as usize..].iter_mut().zip(bytes) {
+                *d &= b;
+            }
+            Ok(())
+        }
+    }
+
+    fn custom() -> Config {
+        let mut profiles = default_profiles();
+        profiles[ProfileId::Home as usize] = Profile {
+            adv_interval: AdvInterval::MAX,
+            tx_power: TxPower::from_dbm(-8),
+            window: AdvWindow::from_secs(5),
+            off_secs: 55,
+            sensors: Sensors {
+                accelerometer: false,
+                ..Sensors::ALL
+            },
+            channels: Channels {
+                ch38: false,
+                ..Channels::ALL
+            },
+        };
+        Config {
+            name_prefix: *b"DOG_\0\0\0\0",
+            lf_clock: LfClock {
+                source: LfClockSource::Rc,
+                accuracy_ppm: 500,
+            },
+            static_address: Some([0x0d, 0x67, 0x62, 0xe8, 0xdb, 0xe2]),
+            active_profile: ProfileId::Travel,
+            profiles,
+        }
+    }
+
+    /// Wraps `body` up as a record of the given version
+    fn wrap(version: u8, body: &[u8]) -> [u8; MAX_RECORD_LEN] {
+        let mut record = [0xff; MAX_RECORD_LEN];
+        record[0..2].copy_from_slice(&MAGIC);
+        record[2] = version;
+        record[3] = body.len() as u8;
+        let crc_at = HEADER_LEN + body.len();
+        record[HEADER_LEN..crc_at].copy_from_slice(body);
+        let crc = crc16(&record[..crc_at]);
+        record[crc_at..crc_at + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn test_blank_flash_gives_defaults() {
+        let mut store = ConfigStore::new(MemFlash::new(), PAGE as u32);
+        assert_eq!(block_on(store.load()), Ok(Config::default()));
+    }
+
+    #[test]
+    fn test_save_then_load() {
+        let mut store = ConfigStore::new(MemFlash::new(), PAGE as u32);
+        block_on(store.save(&Config::default())).unwrap();
+        // Saving again has to erase first or the bits would just get ANDed together
+        block_on(store.save(&custom())).unwrap();
+        assert_eq!(block_on(store.load()), Ok(custom()));
+        assert_eq!(custom().name_prefix(), "DOG_");
+    }
+
+    #[test]
+    fn test_erase_gives_defaults() {
+        let mut store = ConfigStore::new(MemFlash::new(), PAGE as u32);
+        block_on(store.save(&custom())).unwrap();
+        block_on(store.erase()).unwrap();
+        assert_eq!(block_on(store.load()), Ok(Config::default()));
+        // The page before is left alone
+        assert!(store.flash.data[..PAGE].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut store = ConfigStore::new(MemFlash::new(), PAGE as u32);
+        assert_eq!(block_on(store.load_counters()), Ok(None));
+
+        let first = Counters {
+            packet_id: 7,
+            active_minutes: 300,
+        };
+        let second = Counters {
+            packet_id: 8,
+            active_minutes: 301,
+        };
+        assert_eq!(block_on(store.save_counters(&first)), Ok(true));
+        assert_eq!(block_on(store.save_counters(&second)), Ok(true));
+        assert_eq!(block_on(store.load_counters()), Ok(Some(second)));
+
+        // Saving the config keeps them; and they don't get in the way of the config
+        block_on(store.save(&custom())).unwrap();
+        assert_eq!(block_on(store.load_counters()), Ok(Some(second)));
+        assert_eq!(block_on(store.load()), Ok(custom()));
+
+        // Power went halfway through the next one
+        let at = PAGE + COUNTERS_AT as usize + COUNTERS_SLOT_LEN;
+        store.flash.data[at..at + 4].copy_from_slice(&[COUNTERS_MARKER, 9, 0x2e, 0x01]);
+        assert_eq!(block_on(store.load_counters()), Ok(Some(second)));
+        assert_eq!(block_on(store.save_counters(&first)), Ok(true));
+        assert_eq!(block_on(store.load_counters()), Ok(Some(first)));
+    }
+
+    #[test]
+    fn test_counters_full() {
+        let mut store = ConfigStore::new(MemFlash::new(), PAGE as u32);
+        block_on(store.save(&custom())).unwrap();
+        let slots = (PAGE - COUNTERS_AT as usize) / COUNTERS_SLOT_LEN;
+        for i in 0..slots {
+            let counters = Counters {
+                packet_id: i as u8,
+                active_minutes: i as u16,
+            };
+            assert_eq!(block_on(store.save_counters(&counters)), Ok(true));
+        }
+        assert_eq!(
+            block_on(store.save_counters(&Counters::default())),
+            Ok(false)
+        );
+
+        block_on(store.clear_counters()).unwrap();
+        assert_eq!(block_on(store.load_counters()), Ok(None));
+        assert_eq!(block_on(store.load()), Ok(custom()));
+        assert_eq!(
+            block_on(store.save_counters(&Counters::default())),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_with_defaults() {
+        let mut store = ConfigStore::new(MemFlash::new(), PAGE as u32).with_defaults(custom());
+        assert_eq!(block_on(store.load()), Ok(custom()));
+        block_on(store.save(&Config::default())).unwrap();
+        assert_eq!(block_on(store.load()), Ok(Config::default()));
+    }
+
+    #[test]
+    fn test_corruption_gives_defaults() {
+        let mut store = ConfigStore::new(MemFlash::new(), PAGE as u32);
+        block_on(store.save(&custom())).unwrap();
+        store.flash.data[PAGE + HEADER_LEN] ^= 0x01;
+        assert_eq!(block_on(store.load()), Ok(Config::default()));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut record = [0; MAX_RECORD_LEN];
+        let len = custom().encode(&mut record);
+        assert_eq!(Config::decode(&record[..len]), Ok(custom()));
+        assert_eq!(Config::decode(&[0xff; 8]), Err(DecodeError::Blank));
+        assert_eq!(
+            Config::decode(&record[..len - 1]),
+            Err(DecodeError::BadLength)
+        );
+
+        let mut bad_magic = record;
+        bad_magic[0] = b'X';
+        assert_eq!(Config::decode(&bad_magic), Err(DecodeError::BadMagic));
+
+        // A version from the future, with a valid CRC
+        let future = wrap(SCHEMA_VERSION + 1, &record[HEADER_LEN..len - CRC_LEN]);
+        assert_eq!(
+            Config::decode(&future),
+            Err(DecodeError::UnknownVersion(SCHEMA_VERSION + 1))
+        );
+
+        let mut bad_interval = [0; MAX_RECORD_LEN];
+        let body_len = custom().encode_body(&mut bad_interval);
+        // Home profile's interval
+        bad_interval[19..23].copy_from_slice(&16385u32.to_le_bytes());
+        assert_eq!(
+            Config::decode(&wrap(SCHEMA_VERSION, &bad_interval[..body_len])),
+            Err(DecodeError::BadValue)
+        );
+
+        let mut bad_off_secs = [0; MAX_RECORD_LEN];
+        custom().encode_body(&mut bad_off_secs);
+        // Home profile's off_secs
+        bad_off_secs[26..28].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(
+            Config::decode(&wrap(SCHEMA_VERSION, &bad_off_secs[..body_len])),
+            Err(DecodeError::BadValue)
+        );
+
+        let mut bad_tx_power = [0; MAX_RECORD_LEN];
+        custom().encode_body(&mut bad_tx_power);
+        // Home profile's TX power; not a level the radio has
+        bad_tx_power[23] = -10i8 as u8;
+        assert_eq!(
+            Config::decode(&wrap(SCHEMA_VERSION, &bad_tx_power[..body_len])),
+            Err(DecodeError::BadValue)
+        );
+
+        let mut bad_profile = [0; MAX_RECORD_LEN];
+        custom().encode_body(&mut bad_profile);
+        bad_profile[18] = ProfileId::COUNT as u8;
+        assert_eq!(
+            Config::decode(&wrap(SCHEMA_VERSION, &bad_profile[..body_len])),
+            Err(DecodeError::BadValue)
+        );
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        // interval | TX power | window | off_secs | name prefix | LF clock
+        let mut v1 = [0; 20];
+        v1[0..4].copy_from_slice(&16384u32.to_le_bytes());
+        v1[4] = -8i8 as u8;
+        v1[5..7].copy_from_slice(&5u16.to_le_bytes());
+        v1[7..9].copy_from_slice(&55u16.to_le_bytes());
+        v1[9..17].copy_from_slice(b"DOG_\0\0\0\0");
+        v1[17] = LfClockSource::Rc as u8;
+        v1[18..20].copy_from_slice(&500u16.to_le_bytes());
+
+        let mut store = ConfigStore::new(MemFlash::new(), PAGE as u32);
+        let record = wrap(1, &v1);
+        block_on(store.flash.write(PAGE as u32, &record)).unwrap();
+
+        // What v1 had went into the Home profile; everything newer is the defaults
+        let mut expected = Config {
+            static_address: None,
+            active_profile: ProfileId::Home,
+            ..custom()
+        };
+        expected.profiles[ProfileId::Home as usize].sensors = Sensors::ALL;
+        expected.profiles[ProfileId::Home as usize].channels = Channels::ALL;
+        assert_eq!(block_on(store.load()), Ok(expected));
+
+        // The next save is in the current layout
+        block_on(store.save(&expected)).unwrap();
+        assert_eq!(store.flash.data[PAGE + 2], SCHEMA_VERSION);
+        assert_eq!(block_on(store.load()), Ok(expected));
+    }
+
+    #[test]
+    fn test_channels() {
+        assert_eq!(Channels::from_bits(0b111), Ok(Channels::ALL));
+        assert_eq!(Channels::from_bits(0b100).map(Channels::count), Ok(1));
+        assert_eq!(Channels::from_bits(0), Err(DecodeError::BadValue));
+        assert_eq!(Channels::from_bits(0b1000), Err(DecodeError::BadValue));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut config = custom();
+        assert_eq!(
+            config.profile(),
+            &default_profiles()[ProfileId::Travel as usize]
+        );
+
+        config.active_profile = config.active_profile.next().next();
+        assert_eq!(config.active_profile, ProfileId::Home);
+        assert_eq!(config.profile().off_secs, 55);
+
+        // Only the active one changes
+        config.profile_mut().off_secs = 30;
+        assert_eq!(config.profiles[ProfileId::Home as usize].off_secs, 30);
+        assert_eq!(config.profiles[ProfileId::Storage as usize].off_secs, 600);
+    }
+
+    #[test]
+    fn test_static_address() {
+        // e2:db:e8:62:67:0d
+        assert!(is_static_address(&[0x0d, 0x67, 0x62, 0xe8, 0xdb, 0xe2]));
+        // Top bits 10 would be a resolvable private address
+        assert!(!is_static_address(&[0x0d, 0x67, 0x62, 0xe8, 0xdb, 0xa2]));
+        assert!(!is_static_address(&[0, 0, 0, 0, 0, 0xc0]));
+        assert!(!is_static_address(&[0xff; 6]));
+
+        let mut body = [0; MAX_RECORD_LEN];
+        let body_len = custom().encode_body(&mut body);
+        body[17] = 0x22;
+        assert_eq!(
+            Config::decode(&wrap(SCHEMA_VERSION, &body[..body_len])),
+            Err(DecodeError::BadValue)
+        );
+    }
+}
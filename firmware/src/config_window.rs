@@ -0,0 +1,293 @@
+//! A few minutes of connectable advertising with a GATT service for changing the settings in [`Config`].
+//! Opened once after a power up or a reset pin (see [`opens_after`]), and on a long press with the
+//! `button` feature, then it's back to the normal non-connectable broadcast / sleep cycle.
+//! Nothing is authenticated, so the window is only opened when someone is likely to be there with
+//! the tag: never after a watchdog, panic or wake from shelf mode.
+//!
+//! Every characteristic is a little endian copy of the [`Config`] field of the same name; the
+//! interval, TX power, channels, duty cycle and sensor ones are those of the active
+//! [`Profile`](crate::common::config::Profile).
+//! Writing `profile` switches the active profile and the rest then read back that one's settings.
+//! Writes that don't make sense are rejected by putting the current value back.
+//! Changes are saved to flash when the window closes.
+//! The static address is all zeros for "use the chip's own"; it's switched to when the window closes.
+//! Writing [`factory_reset::MAGIC`] to the factory reset characteristic closes the window without saving
+//! and leaves the reset to the caller.
+//! Writing [`shelf::MAGIC`] to the shelf characteristic saves and closes the window, and leaves
+//! going to shelf mode to the caller.
+//! `last_reset` is read-only: why the tag last restarted, see [`reset_reason`].
+//! The LF clock is deliberately not exposed; getting it wrong leaves the tag unable to talk to anyone.
+
+use defmt::{info, unwrap, warn};
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use nrf_softdevice::ble::advertisement_builder::{
+    Flag, LegacyAdvertisementBuilder, LegacyAdvertisementPayload, ServiceList,
+};
+use nrf_softdevice::ble::{gatt_server, peripheral};
+use nrf_softdevice::{Flash, Softdevice};
+
+use crate::common::advertising::{AdvInterval, AdvWindow, TxPower};
+use crate::common::config::{
+    is_static_address, Channels, Config, ConfigStore, ProfileId, NAME_PREFIX_LEN,
+};
+use crate::common::factory_reset;
+use crate::common::power_fail;
+use crate::common::reset_reason::{self, LastReset, ResetCause};
+use crate::common::shelf;
+
+/// How long the window stays open
+pub const WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// b7d10001-5c3a-4c1e-9f0b-6f2a2f5d0e10, little endian for the advertisement
+const SERVICE_UUID: [u8; 16] = [
+    0x10, 0x0e, 0x5d, 0x2f, 0x2a, 0x6f, 0x0b, 0x9f, 0x1e, 0x4c, 0x3a, 0x5c, 0x01, 0x00, 0xd1, 0xb7,
+];
+
+#[nrf_softdevice::gatt_service(uuid = "b7d10001-5c3a-4c1e-9f0b-6f2a2f5d0e10")]
+pub struct ConfigService {
+    /// 0.625ms units; 32 to 16384
+    #[characteristic(uuid = "b7d10002-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    adv_interval: u32,
+    /// dBm; only levels the radio has
+    #[characteristic(uuid = "b7d10003-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    tx_power: i8,
+    /// 1 to 1800
+    #[characteristic(uuid = "b7d10004-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    on_secs: u16,
+    /// At least 1
+    #[characteristic(uuid = "b7d10005-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    off_secs: u16,
+    /// ASCII, padded with 0
+    #[characteristic(uuid = "b7d10006-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    name_prefix: [u8; NAME_PREFIX_LEN],
+    #[characteristic(uuid = "b7d10007-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    accelerometer: bool,
+    /// Least significant byte first; all zeros for none
+    #[characteristic(uuid = "b7d10008-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    static_address: [u8; 6],
+    /// Write-only; see [`factory_reset::MAGIC`]
+    #[characteristic(uuid = "b7d10009-5c3a-4c1e-9f0b-6f2a2f5d0e10", write)]
+    factory_reset: u32,
+    /// 0 home, 1 travel, 2 storage
+    #[characteristic(uuid = "b7d1000a-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    profile: u8,
+    #[characteristic(uuid = "b7d1000b-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    environment: bool,
+    #[characteristic(uuid = "b7d1000c-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    light: bool,
+    /// Write-only; see [`shelf::MAGIC`]
+    #[characteristic(uuid = "b7d1000d-5c3a-4c1e-9f0b-6f2a2f5d0e10", write)]
+    shelf: u32,
+    /// ASCII, padded with 0; e.g. "panic main.rs:42"
+    #[characteristic(uuid = "b7d1000e-5c3a-4c1e-9f0b-6f2a2f5d0e10", read)]
+    last_reset: [u8; reset_reason::DESCRIPTION_LEN],
+    /// Primary advertising channels; bit 0 is 37, at least one
+    #[characteristic(uuid = "b7d1000f-5c3a-4c1e-9f0b-6f2a2f5d0e10", read, write)]
+    channels: u8,
+}
+
+#[nrf_softdevice::gatt_server]
+pub struct Server {
+    config: ConfigService,
+}
+
+impl Server {
+    /// Set once at boot; it doesn't change after.
+    pub fn set_last_reset(&self, last_reset: &LastReset) {
+        let description = last_reset.describe();
+        let mut value = [0; reset_reason::DESCRIPTION_LEN];
+        value[..description.len()].copy_from_slice(description.as_bytes());
+        unwrap!(self.config.last_reset_set(&value));
+    }
+}
+
+/// Whether a reset with `cause` opens the window.
+pub fn opens_after(cause: ResetCause) -> bool {
+    matches!(cause, ResetCause::PowerOn | ResetCause::Pin)
+}
+
+/// How the window closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Closed {
+    TimedOut,
+    /// Nothing was saved; the caller has to do the reset
+    FactoryReset,
+    /// Saved as usual; the caller has to go to shelf mode
+    Shelf,
+}
+
+/// Runs the config window for [`WINDOW`], then saves `app_config` if anything changed.
+/// `name` is what the tag calls itself while the window is open.
+pub async fn open(
+    sd: &'static Softdevice,
+    server: &Server,
+    store: &mut ConfigStore<Flash>,
+    app_config: &mut Config,
+    name: &str,
+) -> Closed {
+    let saved = *app_config;
+    server.config.show(app_config);
+
+    info!("config_window: open for {}s", WINDOW.as_secs());
+    let closed = serve(sd, server, app_config, name, Instant::now() + WINDOW).await;
+    info!("config_window: closed: {}", closed);
+    if closed == Closed::FactoryReset {
+        *app_config = saved;
+        return closed;
+    }
+
+    if *app_config != saved && !power_fail::flash_ok() {
+        warn!("config_window: supply too low to save; changes kept until the next reset");
+    } else if *app_config != saved {
+        info!("config_window: saving {}", app_config);
+        if let Err(e) = store.save(app_config).await {
+            warn!("config_window: unable to save: {}", e);
+        }
+    }
+    closed
+}
+
+/// Advertises and serves connections until `until`.
+/// Nothing is cancelled halfway: advertising ends on the softdevice's own timeout and a connection
+/// still open at `until` is hung up on, so the GATT server finishes whatever write it's on first.
+async fn serve(
+    sd: &'static Softdevice,
+    server: &Server,
+    app_config: &mut Config,
+    name: &str,
+    until: Instant,
+) -> Closed {
+    let adv_data: LegacyAdvertisementPayload = LegacyAdvertisementBuilder::new()
+        .flags(&[Flag::GeneralDiscovery, Flag::LE_Only])
+        .full_name(name)
+        .build();
+    // The 128 bit UUID doesn't fit next to the name
+    let scan_data: LegacyAdvertisementPayload = LegacyAdvertisementBuilder::new()
+        .services_128(ServiceList::Complete, &[SERVICE_UUID])
+        .build();
+
+    loop {
+        // 10ms units
+        let left = until.saturating_duration_since(Instant::now()).as_millis() / 10;
+        if left == 0 {
+            return Closed::TimedOut;
+        }
+        let mut config = peripheral::Config::default();
+        // WINDOW is well under the 655s the softdevice can time
+        config.timeout = Some(left.min(u16::MAX as u64) as u16);
+        let advert = peripheral::ConnectableAdvertisement::ScannableUndirected {
+            adv_data: &adv_data,
+            scan_data: &scan_data,
+        };
+        let conn = match peripheral::advertise_connectable(sd, advert, &config).await {
+            Ok(conn) => conn,
+            Err(peripheral::AdvertiseError::Timeout) => return Closed::TimedOut,
+            Err(e) => defmt::panic!("config_window: unable to advertise: {}", e),
+        };
+        info!("config_window: connected");
+
+        let mut closed = None;
+        let run = gatt_server::run(&conn, server, |e| match e {
+            ServerEvent::Config(ConfigServiceEvent::FactoryResetWrite(factory_reset::MAGIC)) => {
+                closed = Some(Closed::FactoryReset);
+                // Nothing else to do here; hang up so the reset can go ahead
+                let _ = conn.disconnect();
+            }
+            ServerEvent::Config(ConfigServiceEvent::ShelfWrite(shelf::MAGIC)) => {
+                closed = Some(Closed::Shelf);
+                let _ = conn.disconnect();
+            }
+            ServerEvent::Config(e) => server.config.apply(app_config, e),
+        });
+        // Time's up; the server returns once the softdevice reports the disconnect
+        let hang_up = async {
+            Timer::at(until).await;
+            info!("config_window: time's up; disconnecting");
+            let _ = conn.disconnect();
+            core::future::pending::<core::convert::Infallible>().await
+        };
+        let reason = match select(run, hang_up).await {
+            Either::First(reason) => reason,
+            Either::Second(never) => match never {},
+        };
+        info!("config_window: disconnected: {}", reason);
+        if let Some(closed) = closed {
+            return closed;
+        }
+    }
+}
+
+impl ConfigService {
+    /// Makes the characteristics read back what's in `app_config`.
+    fn show(&self, app_config: &Config) {
+        let profile = app_config.profile();
+        unwrap!(self.profile_set(&(app_config.active_profile as u8)));
+        unwrap!(self.adv_interval_set(&profile.adv_interval.units()));
+        unwrap!(self.tx_power_set(&profile.tx_power.dbm()));
+        unwrap!(self.channels_set(&profile.channels.to_bits()));
+        unwrap!(self.on_secs_set(&profile.window.as_secs()));
+        unwrap!(self.off_secs_set(&profile.off_secs));
+        unwrap!(self.accelerometer_set(&profile.sensors.accelerometer));
+        unwrap!(self.environment_set(&profile.sensors.environment));
+        unwrap!(self.light_set(&profile.sensors.light));
+        unwrap!(self.name_prefix_set(&app_config.name_prefix));
+        unwrap!(self.static_address_set(&app_config.static_address.unwrap_or_default()));
+    }
+
+    fn apply(&self, app_config: &mut Config, event: ConfigServiceEvent) {
+        match event {
+            ConfigServiceEvent::ProfileWrite(v) if ProfileId::from_u8(v).is_some() => {
+                app_config.active_profile = unwrap!(ProfileId::from_u8(v));
+                // Everything else now reads back the new profile's settings
+                self.show(app_config);
+            }
+            ConfigServiceEvent::AdvIntervalWrite(v) if AdvInterval::from_units(v).is_some() => {
+                app_config.profile_mut().adv_interval = unwrap!(AdvInterval::from_units(v))
+            }
+            ConfigServiceEvent::TxPowerWrite(v) if TxPower::try_from_dbm(v).is_some() => {
+                app_config.profile_mut().tx_power = unwrap!(TxPower::try_from_dbm(v))
+            }
+            ConfigServiceEvent::ChannelsWrite(v) if Channels::from_bits(v).is_ok() => {
+                app_config.profile_mut().channels = unwrap!(Channels::from_bits(v))
+            }
+            // Zero seconds of advertising would make for a very quiet tag
+            ConfigServiceEvent::OnSecsWrite(v) if AdvWindow::try_from_secs(v).is_some() => {
+                app_config.profile_mut().window = unwrap!(AdvWindow::try_from_secs(v))
+            }
+            // Nor would zero seconds of sleep; the scheduler would spin
+            ConfigServiceEvent::OffSecsWrite(v) if v != 0 => app_config.profile_mut().off_secs = v,
+            ConfigServiceEvent::AccelerometerWrite(v) => {
+                app_config.profile_mut().sensors.accelerometer = v
+            }
+            ConfigServiceEvent::EnvironmentWrite(v) => {
+                app_config.profile_mut().sensors.environment = v
+            }
+            ConfigServiceEvent::LightWrite(v) => app_config.profile_mut().sensors.light = v,
+            ConfigServiceEvent::NamePrefixWrite(v) if is_valid_name_prefix(&v) => {
+                app_config.name_prefix = v
+            }
+            ConfigServiceEvent::StaticAddressWrite([0, 0, 0, 0, 0, 0]) => {
+                app_config.static_address = None
+            }
+            ConfigServiceEvent::StaticAddressWrite(v) if is_static_address(&v) => {
+                app_config.static_address = Some(v)
+            }
+            _ => {
+                warn!("config_window: rejected write");
+                self.show(app_config);
+                return;
+            }
+        }
+        info!("config_window: {}", app_config);
+    }
+}
+
+/// Printable ASCII followed by nothing but padding.
+fn is_valid_name_prefix(prefix: &[u8; NAME_PREFIX_LEN]) -> bool {
+    let len = prefix
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(NAME_PREFIX_LEN);
+    prefix[..len].iter().all(|b| b.is_ascii_graphic()) && prefix[len..].iter().all(|&b| b == 0)
+}
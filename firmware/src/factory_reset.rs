@@ -0,0 +1,85 @@
+//! Back to a known state without a probe. Any one of these does it:
+//!
+//! - holding the button down for [`HOLD_AT_BOOT`] while powering up (with the `button` feature)
+//! - writing [`MAGIC`] to the config window's factory reset characteristic
+//! - [`MAGIC`] in UICR CUSTOMER[0]; it's cleared once the config page is erased
+//!
+//! The config page is erased so everything goes back to what the firmware was built with, the
+//! active minutes count starts over, and the first adverts after carry [`ANNOUNCEMENT`].
+//! There are no keys to wipe yet; when there are, they go here too.
+
+#[cfg(feature = "button")]
+use embassy_futures::select::{select, Either};
+#[cfg(feature = "button")]
+use embassy_nrf::gpio::{AnyPin, Input, Pull};
+#[cfg(feature = "button")]
+use embassy_time::{Duration, Timer};
+
+use defmt::{info, warn};
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::common::bthome::Object;
+use crate::common::config::{self, Config, ConfigStore, DecodeError};
+use crate::common::power_fail;
+use crate::common::uicr;
+
+/// "RSET", little endian
+pub const MAGIC: u32 = 0x5445_5352;
+
+/// Long enough that it won't happen by squeezing the tag while putting the battery in
+#[cfg(feature = "button")]
+pub const HOLD_AT_BOOT: Duration = Duration::from_secs(5);
+
+/// Goes out in place of the usual readings right after a reset
+pub const ANNOUNCEMENT: Object = Object::Text("reset");
+
+/// Whether UICR asks for a reset. The config page is erased right away and the request only
+/// cleared once that's done, so if the power goes in between it all happens again on the next boot.
+/// Has to be called before the softdevice is enabled.
+pub fn requested_by_uicr() -> bool {
+    if uicr::customer(uicr::FACTORY_RESET) != MAGIC {
+        return false;
+    }
+    info!("factory_reset: requested by UICR; erasing config");
+    uicr::erase_page(config::offset());
+    // NVMC doesn't say whether an erase worked; check what's left
+    match config::read_mapped() {
+        Err(DecodeError::Blank) => uicr::clear_customer(uicr::FACTORY_RESET),
+        _ => warn!("factory_reset: config not erased; trying again next boot"),
+    }
+    true
+}
+
+/// Whether the button (wired to ground) is held down for [`HOLD_AT_BOOT`].
+#[cfg(feature = "button")]
+pub async fn requested_by_button(pin: &mut AnyPin) -> bool {
+    let mut button = Input::new(pin, Pull::Up);
+    if button.is_high() {
+        return false;
+    }
+    info!("factory_reset: button down at boot; keep holding");
+    match select(button.wait_for_high(), Timer::after(HOLD_AT_BOOT)).await {
+        Either::First(_) => false,
+        Either::Second(_) => {
+            info!("factory_reset: requested by button");
+            true
+        }
+    }
+}
+
+/// Erases the config page and returns the defaults (provisioned values included) to carry on with.
+pub async fn run<F: NorFlash>(store: &mut ConfigStore<F>) -> Config {
+    if !power_fail::flash_ok() {
+        warn!("factory_reset: supply too low to erase; using the defaults until the next reset");
+        return store.defaults();
+    }
+    info!("factory_reset: erasing config");
+    if let Err(e) = store.erase().await {
+        // Nothing else to do about it; the defaults are still used until the next boot
+        warn!(
+            "factory_reset: unable to erase: {}",
+            defmt::Debug2Format(&e)
+        );
+    }
+    store.defaults()
+}
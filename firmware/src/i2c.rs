@@ -0,0 +1,41 @@
+//! The TWIM peripheral and its pins, shared by everything on the I2C bus (accelerometer, environmental sensors...).
+//! Same pattern as the SAADC in the main loop: nobody keeps the peripheral enabled between uses.
+//! Lock the bus, bring up a `Twim`, use it, drop it.
+
+use embassy_nrf::gpio::AnyPin;
+use embassy_nrf::twim::{self, Twim};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+
+// The names differ between chips; board sorts that out
+use crate::common::board::{Twim0, Twim0Irqs};
+
+pub struct Bus {
+    twim: Twim0,
+    sda: AnyPin,
+    scl: AnyPin,
+}
+
+/// `None` until the application hands over the peripheral with [`init`].
+pub static BUS: Mutex<CriticalSectionRawMutex, Option<Bus>> = Mutex::new(None);
+
+pub async fn init(twim: Twim0, sda: AnyPin, scl: AnyPin) {
+    *BUS.lock().await = Some(Bus { twim, sda, scl });
+}
+
+impl Bus {
+    /// Brings the bus up; it goes back down when the returned `Twim` is dropped.
+    pub fn twim(&mut self) -> Twim<'_, Twim0> {
+        // The pull-ups only draw current while the bus is up
+        let mut config = twim::Config::default();
+        config.sda_pullup = true;
+        config.scl_pullup = true;
+        Twim::new(
+            &mut self.twim,
+            Twim0Irqs,
+            &mut self.sda,
+            &mut self.scl,
+            config,
+        )
+    }
+}
@@ -0,0 +1,16 @@
+//! The shared code as a library, only so its unit tests can run on the host; see "Tests" in the
+//! readme. The binaries don't use it, they pull in `common.rs` by path.
+//! For anything but `cargo test` this is an empty crate.
+
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(test)]
+#[path = "common.rs"]
+pub mod common;
+
+/// defmt's `panic!` and `unwrap!` end up here in the tests; on the chip it's panic-probe's job.
+#[cfg(test)]
+#[defmt::panic_handler]
+fn defmt_panic() -> ! {
+    panic!("defmt panic")
+}
@@ -0,0 +1,18 @@
+//! Global logger for `production` builds, in place of `defmt-rtt`: nothing goes anywhere and no
+//! RTT buffer is set aside. With DEFMT_LOG=off (build.rs insists) the log calls are compiled out
+//! and this is never called; it's only here so defmt still has a logger to link against.
+//! The host tests use it too; there's no RTT to log to there.
+
+#[defmt::global_logger]
+struct NullLogger;
+
+// SAFETY: there's no state to protect
+unsafe impl defmt::Logger for NullLogger {
+    fn acquire() {}
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {}
+
+    unsafe fn write(_bytes: &[u8]) {}
+}
@@ -0,0 +1,17 @@
+//! Panic handler for tags out in the field: log what happened, leave a note for the next boot
+//! (see [`reset_reason`](crate::common::reset_reason)), then reset the chip and carry on.
+//! `panic-probe` (the default) stops and waits for a debugger instead, which is what you want at
+//! the bench and the last thing you want on a dog.
+
+use core::panic::PanicInfo;
+
+use defmt::error;
+
+use crate::common::reset_reason;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    error!("{}", defmt::Display2Format(info));
+    reset_reason::record_panic(info);
+    cortex_m::peripheral::SCB::sys_reset()
+}
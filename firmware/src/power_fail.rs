@@ -0,0 +1,71 @@
+//! Power-fail comparator: a warning from the softdevice that the supply is dropping towards
+//! brown-out, with time left to put the counters somewhere safe and shut down cleanly rather than
+//! browning out halfway through a flash write.
+//!
+//! Flash writes need the supply to hold up until they're done, so anything that writes flash checks
+//! [`flash_ok`] first; a low battery reading is enough to skip the write even before a warning.
+
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use defmt::{info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use nrf_softdevice::{raw, SocEvent};
+
+/// Below this, flash writes aren't started.
+/// TODO: a guess with some margin over the 1.7V the datasheet needs for NVMC writes; not measured
+pub const MIN_FLASH_MV: u16 = 1900;
+
+/// Comparator threshold.
+/// TODO: a guess; high enough above MIN_FLASH_MV to get a counters write and an advert out
+const THRESHOLD: u32 = raw::NRF_POWER_THRESHOLDS_NRF_POWER_THRESHOLD_V21;
+
+static FAILING: AtomicBool = AtomicBool::new(false);
+static FAILED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Last battery reading; 0 until there is one, which doesn't hold up writes at boot
+static BATTERY_MV: AtomicU16 = AtomicU16::new(0);
+
+/// Turns the comparator on; the softdevice has to be enabled and its task running.
+pub fn enable() {
+    // SAFETY: plain softdevice calls with in-range arguments
+    let ret = unsafe {
+        match raw::sd_power_pof_threshold_set(THRESHOLD as u8) {
+            raw::NRF_SUCCESS => raw::sd_power_pof_enable(1),
+            err => err,
+        }
+    };
+    if ret == raw::NRF_SUCCESS {
+        info!("power fail: warning enabled");
+    } else {
+        warn!("power fail: unable to enable: {}", ret);
+    }
+}
+
+/// For `Softdevice::run_with_callback`.
+pub fn on_soc_event(event: SocEvent) {
+    if let SocEvent::PowerFailureWarning = event {
+        FAILING.store(true, Ordering::Relaxed);
+        FAILED.signal(());
+    }
+}
+
+/// Keeps the last battery reading for [`flash_ok`].
+pub fn set_battery_millivolts(mv: u16) {
+    BATTERY_MV.store(mv, Ordering::Relaxed);
+}
+
+/// There's been a warning; the only thing left to do is shut down.
+pub fn is_failing() -> bool {
+    FAILING.load(Ordering::Relaxed)
+}
+
+/// Waits for a warning.
+pub async fn wait() {
+    FAILED.wait().await
+}
+
+/// Whether there's enough supply left to start a flash write.
+pub fn flash_ok() -> bool {
+    let mv = BATTERY_MV.load(Ordering::Relaxed);
+    !is_failing() && (mv == 0 || mv >= MIN_FLASH_MV)
+}
@@ -0,0 +1,240 @@
+//! Why the last reset happened, for tags that reboot out in the field with nobody watching.
+//!
+//! The cause comes from the POWER peripheral's RESETREAS register. A panic (with the `panic-reset`
+//! feature) also leaves where it happened in a record in `.uninit` RAM, which survives the soft
+//! reset that follows. Both are read and cleared once at boot; the cause goes out as a BTHome text
+//! object with the first round of adverts, and the whole thing can be read from the config window.
+//!
+//! There's no bit for a brown-out; it looks the same as putting a battery in.
+//! Panics from defmt's macros (`unwrap!` and friends) are recorded as wherever defmt ends up
+//! calling `panic!`; the message itself is only ever in the log.
+
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+
+use arrayvec::ArrayString;
+
+/// POWER RESETREAS; same place on the 52810 and 52832
+const RESETREAS: *mut u32 = 0x4000_0400 as *mut u32;
+
+/// RESETREAS bits
+const RESETPIN: u32 = 1 << 0;
+const DOG: u32 = 1 << 1;
+const SREQ: u32 = 1 << 2;
+const LOCKUP: u32 = 1 << 3;
+const OFF: u32 = 1 << 16;
+const LPCOMP: u32 = 1 << 17;
+const DIF: u32 = 1 << 18;
+
+/// "PANC", little endian
+const PANIC_MAGIC: u32 = 0x434e_4150;
+/// File name and line; long paths are cut short from the front
+pub const LOCATION_LEN: usize = 24;
+/// Room for the longest cause and a location
+pub const DESCRIPTION_LEN: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PanicRecord {
+    magic: u32,
+    len: u8,
+    location: [u8; LOCATION_LEN],
+}
+
+impl PanicRecord {
+    /// Where it panicked, if this is a record at all and not whatever was in RAM at power up.
+    fn location(&self) -> Option<&str> {
+        if self.magic != PANIC_MAGIC || self.len as usize > LOCATION_LEN {
+            return None;
+        }
+        core::str::from_utf8(&self.location[..self.len as usize]).ok()
+    }
+}
+
+/// Not touched by the startup code, so it's still there after a soft reset
+#[link_section = ".uninit.reset_reason.PANIC"]
+static mut PANIC: MaybeUninit<PanicRecord> = MaybeUninit::uninit();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ResetCause {
+    /// Battery in, or a brown-out
+    PowerOn,
+    /// Reset pin
+    Pin,
+    Watchdog,
+    /// Panic with `panic-reset`
+    Panic,
+    /// Soft reset that wasn't a panic
+    Software,
+    /// CPU lockup
+    Lockup,
+    /// Woken from System OFF (shelf mode)
+    WakeUp,
+    /// Debugger
+    Debug,
+}
+
+impl ResetCause {
+    /// More than one bit is only ever set if the register wasn't cleared; the most telling one wins.
+    pub fn from_resetreas(resetreas: u32, panicked: bool) -> Self {
+        if resetreas & DOG != 0 {
+            ResetCause::Watchdog
+        } else if resetreas & LOCKUP != 0 {
+            ResetCause::Lockup
+        } else if resetreas & SREQ != 0 && panicked {
+            ResetCause::Panic
+        } else if resetreas & SREQ != 0 {
+            ResetCause::Software
+        } else if resetreas & RESETPIN != 0 {
+            ResetCause::Pin
+        } else if resetreas & (OFF | LPCOMP) != 0 {
+            ResetCause::WakeUp
+        } else if resetreas & DIF != 0 {
+            ResetCause::Debug
+        } else {
+            ResetCause::PowerOn
+        }
+    }
+
+    /// Short enough to go out as a BTHome text object next to the packet ID
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "power",
+            ResetCause::Pin => "pin",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::Panic => "panic",
+            ResetCause::Software => "software",
+            ResetCause::Lockup => "lockup",
+            ResetCause::WakeUp => "wake",
+            ResetCause::Debug => "debug",
+        }
+    }
+}
+
+/// Not `defmt::Format`; log [`describe`](Self::describe) instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LastReset {
+    pub cause: ResetCause,
+    pub panic_location: Option<ArrayString<LOCATION_LEN>>,
+}
+
+impl LastReset {
+    /// Reads and clears RESETREAS and the panic record so the next reset starts from nothing.
+    /// Has to be called before the softdevice is enabled; after that POWER belongs to it.
+    pub fn take() -> Self {
+        // SAFETY: POWER is always mapped and nothing else uses it yet; PANIC is only written by the
+        // panic handler, which isn't coming back to this boot
+        // Any bit pattern is a valid PanicRecord; location() sorts out the ones that mean something
+        let (resetreas, record) = unsafe {
+            let resetreas = core::ptr::read_volatile(RESETREAS);
+            // Write 1 to clear
+            core::ptr::write_volatile(RESETREAS, resetreas);
+            let panic = addr_of_mut!(PANIC).cast::<PanicRecord>();
+            let record = core::ptr::read_volatile(panic);
+            core::ptr::write_volatile(addr_of_mut!((*panic).magic), 0);
+            (resetreas, record)
+        };
+        Self::decode(resetreas, &record)
+    }
+
+    fn decode(resetreas: u32, record: &PanicRecord) -> Self {
+        // A record without a soft reset is left over from some earlier boot
+        let panic_location = record
+            .location()
+            .filter(|_| resetreas & SREQ != 0)
+            .and_then(|location| ArrayString::from(location).ok());
+        Self {
+            cause: ResetCause::from_resetreas(resetreas, panic_location.is_some()),
+            panic_location,
+        }
+    }
+
+    /// For people rather than Home Assistant, e.g. "panic main.rs:42"
+    pub fn describe(&self) -> ArrayString<DESCRIPTION_LEN> {
+        let mut description = ArrayString::new();
+        description.push_str(self.cause.as_str());
+        if let Some(location) = self.panic_location {
+            description.push(' ');
+            description.push_str(&location);
+        }
+        description
+    }
+}
+
+/// Leaves where the panic happened for the next boot; for the panic handler only.
+pub fn record_panic(info: &PanicInfo) {
+    let mut location = ArrayString::<LOCATION_LEN>::new();
+    if let Some(l) = info.location() {
+        let file = l.file().rsplit(['/', '\\']).next().unwrap_or_default();
+        // Keep the end of the file name and the whole line number; line numbers are at most 10 digits
+        let mut start = file.len().saturating_sub(LOCATION_LEN - 11);
+        while !file.is_char_boundary(start) {
+            start += 1;
+        }
+        let _ = write!(location, "{}:{}", &file[start..], l.line());
+    }
+    let mut record = PanicRecord {
+        magic: PANIC_MAGIC,
+        len: location.len() as u8,
+        location: [0; LOCATION_LEN],
+    };
+    record.location[..location.len()].copy_from_slice(location.as_bytes());
+    // SAFETY: nothing else runs once we're panicking
+    unsafe { core::ptr::write_volatile(addr_of_mut!(PANIC).cast::<PanicRecord>(), record) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(location: &str) -> PanicRecord {
+        let mut record = PanicRecord {
+            magic: PANIC_MAGIC,
+            len: location.len() as u8,
+            location: [0; LOCATION_LEN],
+        };
+        record.location[..location.len()].copy_from_slice(location.as_bytes());
+        record
+    }
+
+    #[test]
+    fn test_cause() {
+        assert_eq!(ResetCause::from_resetreas(0, false), ResetCause::PowerOn);
+        assert_eq!(ResetCause::from_resetreas(DOG, false), ResetCause::Watchdog);
+        assert_eq!(ResetCause::from_resetreas(RESETPIN, false), ResetCause::Pin);
+        assert_eq!(ResetCause::from_resetreas(OFF, false), ResetCause::WakeUp);
+        // The watchdog doesn't care that we panicked on the way
+        assert_eq!(
+            ResetCause::from_resetreas(DOG | SREQ, true),
+            ResetCause::Watchdog
+        );
+    }
+
+    #[test]
+    fn test_panic_record() {
+        let last = LastReset::decode(SREQ, &record("main.rs:42"));
+        assert_eq!(last.cause, ResetCause::Panic);
+        assert_eq!(last.describe().as_str(), "panic main.rs:42");
+
+        // Left over from a panic before the battery was swapped
+        let last = LastReset::decode(0, &record("main.rs:42"));
+        assert_eq!(last.cause, ResetCause::PowerOn);
+        assert_eq!(last.panic_location, None);
+
+        // Whatever was in RAM at power up
+        let mut garbage = record("main.rs:42");
+        garbage.magic = 0xdead_beef;
+        assert_eq!(
+            LastReset::decode(SREQ, &garbage).cause,
+            ResetCause::Software
+        );
+        let mut garbage = record("main.rs:42");
+        garbage.len = 0xff;
+        assert_eq!(
+            LastReset::decode(SREQ, &garbage).cause,
+            ResetCause::Software
+        );
+    }
+}
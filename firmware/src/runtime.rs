@@ -0,0 +1,389 @@
+//! Everything a binary needs before it can advertise: the board, the softdevice and its task, and
+//! who we are on the air.
+//!
+//! ```ignore
+//! let (rt, board) = Runtime::builder().start(spawner);
+//! // or, with a GATT server:
+//! let (rt, board, server) = Runtime::builder()
+//!     .connectable(1024)
+//!     .start_with(spawner, |sd| unwrap!(Server::new(sd)));
+//! let name = rt.device_name(&app_config.name_template());
+//! rt.advertise(&adv_data, &peripheral::Config::default()).await;
+//! ```
+
+use arrayvec::ArrayString;
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Waker};
+
+use defmt::{debug, info, unwrap, warn};
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Instant, Timer};
+use nrf_softdevice::ble::peripheral::AdvertiseError;
+use nrf_softdevice::ble::{self, peripheral, Address, AddressType};
+use nrf_softdevice::{raw, Softdevice};
+
+use crate::common::advertising::{AdvParams, TxPower};
+use crate::common::board::{self, Board};
+use crate::common::config::{Channels, Config, LfClock};
+use crate::common::power_fail;
+use crate::common::scheduler::adv_timeout;
+use crate::common::util::encoding::{NameSource, NameTemplate, MAX_NAME_LEN};
+
+/// FICR DEVICEID[0..2]; same place on the 52810 and 52832
+const FICR_DEVICEID: *const [u32; 2] = 0x1000_0060 as *const [u32; 2];
+
+/// The softdevice hands out 0 for the first advertising set, and S112 only has the one. It's
+/// nrf-softdevice that makes it; see [`Runtime::make_adv_set`]
+const ADV_SET: u8 = 0;
+
+#[embassy_executor::task]
+async fn softdevice_task(sd: &'static Softdevice) -> ! {
+    sd.run_with_callback(power_fail::on_soc_event).await
+}
+
+/// Softdevice settings that differ between binaries; everything else is the same for all of them.
+pub struct Builder {
+    lf_clock: LfClock,
+    event_length: u16,
+    /// `None` for advertise-only
+    attr_tab_size: Option<u32>,
+}
+
+impl Builder {
+    /// Defaults to the board's LF clock.
+    pub fn lf_clock(mut self, lf_clock: LfClock) -> Self {
+        self.lf_clock = lf_clock;
+        self
+    }
+
+    /// Count of 1.25ms units set aside for a connection.
+    pub fn event_length(mut self, event_length: u16) -> Self {
+        self.event_length = event_length;
+        self
+    }
+
+    /// Room for one connection and a GATT attribute table of `attr_tab_size` bytes.
+    /// Without this the softdevice is configured for advertising only, which takes the least RAM.
+    pub fn connectable(mut self, attr_tab_size: u32) -> Self {
+        self.attr_tab_size = Some(attr_tab_size);
+        self
+    }
+
+    /// Brings up the board, enables the softdevice and spawns its task.
+    pub fn start(self, spawner: Spawner) -> (Runtime, Board) {
+        let (rt, board, ()) = self.start_with(spawner, |_| ());
+        (rt, board)
+    }
+
+    /// [`start`](Self::start), with a chance to register GATT servers in between enabling the
+    /// softdevice and spawning its task; that's the only time it can be had as `&mut`.
+    pub fn start_with<T>(
+        self,
+        spawner: Spawner,
+        register: impl FnOnce(&mut Softdevice) -> T,
+    ) -> (Runtime, Board, T) {
+        let (board, sd_config) = board::init(&self.lf_clock, self.event_length, self.attr_tab_size);
+        debug!("board::init: done!");
+
+        let sd = Softdevice::enable(&sd_config);
+        debug!("Softdevice: enabled...");
+        let registered = register(sd);
+        let sd: &'static Softdevice = sd;
+        unwrap!(spawner.spawn(softdevice_task(sd)));
+        debug!("Softdevice: running...");
+
+        let rt = Runtime {
+            sd,
+            own_address: ble::get_address(sd),
+        };
+        info!("address: {}", rt.own_address);
+        (rt, board, registered)
+    }
+}
+
+/// Handle to the running softdevice; advertise through this.
+#[derive(Clone, Copy)]
+pub struct Runtime {
+    pub sd: &'static Softdevice,
+    /// The one the softdevice came up with, from FICR
+    own_address: Address,
+}
+
+impl Runtime {
+    pub fn builder() -> Builder {
+        Builder {
+            lf_clock: board::LF_CLOCK,
+            event_length: 24,
+            attr_tab_size: None,
+        }
+    }
+
+    /// The address we advertise with.
+    pub fn address(&self) -> Address {
+        ble::get_address(self.sd)
+    }
+
+    /// Switches to the static address `app_config` asks for (or back to our own) and returns the
+    /// matching device name. Not while advertising or connected.
+    pub fn apply_identity(&self, app_config: &Config) -> ArrayString<MAX_NAME_LEN> {
+        let address = match app_config.static_address {
+            Some(bytes) => Address::new(AddressType::RandomStatic, bytes),
+            None => self.own_address,
+        };
+        if address != self.address() {
+            info!("address: {}", address);
+            ble::set_address(self.sd, &address);
+        }
+        self.device_name(&app_config.name_template())
+    }
+
+    /// The chip's factory assigned 64 bit ID, least significant byte first.
+    pub fn device_id(&self) -> [u8; 8] {
+        // SAFETY: FICR is always mapped and read only
+        let words = unsafe { core::ptr::read_volatile(FICR_DEVICEID) };
+        let mut id = [0; 8];
+        id[..4].copy_from_slice(&words[0].to_le_bytes());
+        id[4..].copy_from_slice(&words[1].to_le_bytes());
+        id
+    }
+
+    /// A seed for anything that just needs tags to behave differently from each other.
+    /// From the RNG by way of the softdevice, which owns it; the device ID if its pool is empty.
+    pub fn random_seed(&self) -> u32 {
+        let mut bytes = [0; 4];
+        match nrf_softdevice::random_bytes(self.sd, &mut bytes) {
+            Ok(()) => u32::from_le_bytes(bytes),
+            Err(e) => {
+                debug!("random_seed: no random bytes yet: {}", e);
+                let id = self.device_id();
+                u32::from_le_bytes([id[0], id[1], id[2], id[3]])
+                    ^ u32::from_le_bytes([id[4], id[5], id[6], id[7]])
+            }
+        }
+    }
+
+    /// The name from `template`, with the suffix from wherever it says.
+    pub fn device_name(&self, template: &NameTemplate) -> ArrayString<MAX_NAME_LEN> {
+        let name = match template.source {
+            // Note that endianness is reversed in the MAC address
+            // If device address is e2:db:e8:62:67:0d, `bytes()` will be:
+            //      RandomStatic:[0d, 67, 62, e8, db, e2]
+            NameSource::Address => template.render(&self.address().bytes()),
+            NameSource::DeviceId => template.render(&self.device_id()),
+        };
+        // Prefix length and suffix bytes are both checked long before we get here
+        unwrap!(name)
+    }
+
+    /// Non-connectable, non-scannable advertising of `adv_data`, forever.
+    /// `config` shouldn't have a `timeout` or `max_events`; if it does, advertising starts over
+    /// each time one runs out.
+    pub async fn advertise(&self, adv_data: &[u8], config: &peripheral::Config) -> ! {
+        loop {
+            self.advertise_once(adv_data, config).await;
+        }
+    }
+
+    /// Like [`advertise`](Self::advertise) but the softdevice stops at `until`, or once
+    /// `config.max_events` adverts have gone out if that comes first.
+    pub async fn advertise_until(
+        &self,
+        adv_data: &[u8],
+        mut config: peripheral::Config,
+        channels: Channels,
+        until: Instant,
+    ) {
+        if channels != Channels::ALL && self.advertise_on(adv_data, &config, channels, until).await
+        {
+            return;
+        }
+        // Windows longer than the longest timeout take more than one go
+        loop {
+            let now = Instant::now();
+            if now >= until {
+                return;
+            }
+            config.timeout = Some(adv_timeout(until - now));
+            if !self.advertise_once(adv_data, &config).await {
+                return;
+            }
+        }
+    }
+
+    /// [`advertise_until`](Self::advertise_until) on just `channels`. nrf-softdevice always uses
+    /// all three, so this goes to the softdevice directly, with the same advertising set.
+    /// The softdevice still ends each go (`duration` and `max_adv_evts`), but only nrf-softdevice
+    /// gets to see the event that says so; this wakes up at the end of the go to carry on.
+    /// If `max_events` runs out first, the radio is off for whatever is left of it.
+    /// Returns `false` without advertising if the softdevice won't have it.
+    async fn advertise_on(
+        &self,
+        adv_data: &[u8],
+        config: &peripheral::Config,
+        channels: Channels,
+        until: Instant,
+    ) -> bool {
+        info!("advertise: advertising on {}...", channels);
+        debug!(
+            "advertise: adv_data({}): {=[u8]:02x}",
+            adv_data.len(),
+            adv_data
+        );
+        loop {
+            let now = Instant::now();
+            if now >= until {
+                break;
+            }
+            let timeout = adv_timeout(until - now);
+            let mut ret = start_raw(adv_data, config, channels, timeout);
+            if ret == raw::BLE_ERROR_INVALID_ADV_HANDLE {
+                self.make_adv_set(adv_data, config);
+                ret = start_raw(adv_data, config, channels, timeout);
+            }
+            if ret != raw::NRF_SUCCESS {
+                warn!("advertise: unable to pick channels: {}", ret);
+                return false;
+            }
+            // Stops it early if this is dropped; a no-op once the softdevice has stopped it
+            let _stop = StopOnDrop(ADV_SET);
+            Timer::at(until.min(now + Duration::from_millis(u64::from(timeout) * 10))).await;
+            // Another go would be another `max_events`
+            if config.max_events.is_some() {
+                break;
+            }
+        }
+        info!("advertise: stop advertising...");
+        true
+    }
+
+    /// Has nrf-softdevice make its advertising set, so [`start_raw`] can use it: S112 only has the
+    /// one, so if it were made here nrf-softdevice couldn't make its own for the config window.
+    /// It gets started and stopped within the same poll, before the first advert would go out.
+    fn make_adv_set(&self, adv_data: &[u8], config: &peripheral::Config) {
+        debug!("advertise: making the advertising set");
+        let advert = peripheral::NonconnectableAdvertisement::NonscannableUndirected { adv_data };
+        let advertise = pin!(peripheral::advertise(self.sd, advert, config));
+        // Dropping it is what stops it
+        let _ = advertise.poll(&mut Context::from_waker(Waker::noop()));
+    }
+
+    /// One go at advertising; whether it was the timeout that ended it (as opposed to `max_events`).
+    async fn advertise_once(&self, adv_data: &[u8], config: &peripheral::Config) -> bool {
+        let advert = peripheral::NonconnectableAdvertisement::NonscannableUndirected { adv_data };
+        info!("advertise: advertising...");
+        info!(
+            "advertise: adv_data({}): {=[u8]:02x}",
+            adv_data.len(),
+            adv_data
+        );
+        // The softdevice reports running out of time and running out of events the same way
+        match peripheral::advertise(self.sd, advert, config).await {
+            Err(AdvertiseError::Timeout) => {
+                info!("advertise: stop advertising...");
+                config.max_events.is_none()
+            }
+            other => {
+                // Without a timeout or max_events, nothing else ends it
+                unwrap!(other);
+                false
+            }
+        }
+    }
+}
+
+/// Starts advertising `adv_data` on just `channels` for `timeout` (10ms units) with the
+/// advertising set nrf-softdevice made; `BLE_ERROR_INVALID_ADV_HANDLE` if it hasn't yet.
+fn start_raw(
+    adv_data: &[u8],
+    config: &peripheral::Config,
+    channels: Channels,
+    timeout: u16,
+) -> u32 {
+    let mut handle = ADV_SET;
+    let data = raw::ble_gap_adv_data_t {
+        adv_data: raw::ble_data_t {
+            // Only ever read by the softdevice
+            p_data: adv_data.as_ptr() as *mut u8,
+            len: adv_data.len() as u16,
+        },
+        scan_rsp_data: raw::ble_data_t {
+            p_data: core::ptr::null_mut(),
+            len: 0,
+        },
+    };
+    // SAFETY: all zeros is a valid (if useless) set of parameters; the ones that matter follow
+    let mut params: raw::ble_gap_adv_params_t = unsafe { core::mem::zeroed() };
+    params.properties.type_ = raw::BLE_GAP_ADV_TYPE_NONCONNECTABLE_NONSCANNABLE_UNDIRECTED as u8;
+    params.primary_phy = raw::BLE_GAP_PHY_1MBPS as u8;
+    params.interval = config.interval;
+    params.channel_mask = channel_mask(channels);
+    params.duration = timeout;
+    params.max_adv_evts = config.max_events.unwrap_or(0);
+
+    // SAFETY: `data` points into `adv_data`, which outlives the advertising; the caller stops it
+    // before giving `adv_data` back
+    unsafe {
+        match raw::sd_ble_gap_adv_set_configure(&mut handle, &data, &params) {
+            raw::NRF_SUCCESS => match raw::sd_ble_gap_tx_power_set(
+                raw::BLE_GAP_TX_POWER_ROLES_BLE_GAP_TX_POWER_ROLE_ADV as u8,
+                handle.into(),
+                config.tx_power as i8,
+            ) {
+                raw::NRF_SUCCESS => {
+                    raw::sd_ble_gap_adv_start(handle, raw::BLE_CONN_CFG_TAG_DEFAULT as u8)
+                }
+                err => err,
+            },
+            err => err,
+        }
+    }
+}
+
+/// Stops raw advertising however [`Runtime::advertise_on`] ends.
+struct StopOnDrop(u8);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        // SAFETY: plain softdevice call; stopping twice just returns an error
+        unsafe { raw::sd_ble_gap_adv_stop(self.0) };
+    }
+}
+
+/// The softdevice's channel mask: one bit per channel, 0 to 39, set for the ones *not* to use.
+fn channel_mask(channels: Channels) -> raw::ble_gap_ch_mask_t {
+    let mut mask = [0; 5];
+    // Channels 37, 38 and 39 are the top three bits
+    mask[4] = (!channels.to_bits() & 0b111) << 5;
+    mask
+}
+
+/// The one place our advertising settings become the softdevice's.
+impl From<AdvParams> for peripheral::Config {
+    fn from(params: AdvParams) -> Self {
+        peripheral::Config {
+            interval: params.interval.units(),
+            tx_power: params.tx_power.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<TxPower> for ble::TxPower {
+    fn from(power: TxPower) -> Self {
+        // `TxPower` only ever holds one of these
+        match power.dbm() {
+            -40 => ble::TxPower::Minus40dBm,
+            -20 => ble::TxPower::Minus20dBm,
+            -16 => ble::TxPower::Minus16dBm,
+            -12 => ble::TxPower::Minus12dBm,
+            -8 => ble::TxPower::Minus8dBm,
+            -4 => ble::TxPower::Minus4dBm,
+            #[cfg(feature = "nrf52832")]
+            3 => ble::TxPower::Plus3dBm,
+            #[cfg(feature = "nrf52832")]
+            4 => ble::TxPower::Plus4dBm,
+            _ => ble::TxPower::ZerodBm,
+        }
+    }
+}
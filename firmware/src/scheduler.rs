@@ -0,0 +1,229 @@
+//! The broadcast / sleep cycle as explicit phases:
+//!
+//! ```text
+//! Measure -> Advertise (on) -> Sleep (off) -> Measure -> ...
+//! ```
+//!
+//! Nothing here waits; the caller does whatever the current phase asks for, then calls
+//! [`Scheduler::advance`]. Advertising windows are ended by the softdevice itself (see
+//! [`adv_timeout`]) rather than by dropping the advertising future.
+//!
+//! Tags powered up together would otherwise keep their windows lined up and keep colliding at the
+//! same proxies; [`Jitter`] moves every window's length and the sleep before the next one by a
+//! random amount either side of nominal, so the average duty cycle stays what it was.
+
+use embassy_time::{Duration, Instant};
+
+use crate::common::advertising::AdvWindow;
+
+/// Largest timeout the softdevice takes, in its 10ms units
+const MAX_ADV_TIMEOUT: u16 = u16::MAX;
+
+/// Longest an advertising window or a sleep can be set to, before jitter.
+/// Also how long a hang partway through one could go before the [watchdog](crate::common::watchdog)
+/// notices.
+pub const MAX_PHASE: Duration = Duration::from_secs(30 * 60);
+/// Most jitter [`Jitter`] will apply, in percent
+pub const MAX_JITTER_PERCENT: u8 = 50;
+
+/// How long to advertise, then how long to be quiet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct DutyCycle {
+    pub on: Duration,
+    pub off: Duration,
+}
+
+impl DutyCycle {
+    /// A sleep over [`MAX_PHASE`] is cut down to it; a window can't be any longer to begin with.
+    pub fn new(window: AdvWindow, off_secs: u16) -> Self {
+        Self {
+            on: window.duration(),
+            off: Duration::from_secs(off_secs.into()).min(MAX_PHASE),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Phase {
+    /// Take readings and build the next advert
+    Measure,
+    /// Advertise what was measured until then
+    Advertise { until: Instant },
+    /// Radio off until then; a button press may end it early
+    Sleep { until: Instant },
+}
+
+pub struct Scheduler {
+    phase: Phase,
+    jitter: Jitter,
+}
+
+impl Scheduler {
+    /// Starts with a measurement so the first advert has something in it. No jitter.
+    pub const fn new() -> Self {
+        Self {
+            phase: Phase::Measure,
+            jitter: Jitter::NONE,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Call once the current phase is done, however it ended; returns the next one.
+    /// Each phase is timed from `now` so a late end pushes the rest back instead of eating into it.
+    pub fn advance(&mut self, now: Instant, duty_cycle: DutyCycle) -> Phase {
+        self.phase = match self.phase {
+            Phase::Measure => Phase::Advertise {
+                until: now + self.jitter.apply(duty_cycle.on),
+            },
+            Phase::Advertise { .. } => Phase::Sleep {
+                until: now + self.jitter.apply(duty_cycle.off),
+            },
+            Phase::Sleep { .. } => Phase::Measure,
+        };
+        self.phase
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Moves durations by up to a percentage either way, evenly spread so it averages out to nothing.
+pub struct Jitter {
+    percent: u8,
+    rng: XorShift,
+}
+
+impl Jitter {
+    pub const NONE: Self = Self {
+        percent: 0,
+        rng: XorShift(1),
+    };
+
+    /// Up to `percent` (at most [`MAX_JITTER_PERCENT`]) either way; `seed` should differ from tag to tag.
+    pub fn new(percent: u8, seed: u32) -> Self {
+        Self {
+            percent: percent.min(MAX_JITTER_PERCENT),
+            // Stuck at 0 forever otherwise
+            rng: XorShift(u64::from(seed) | (1 << 32)),
+        }
+    }
+
+    pub fn apply(&mut self, duration: Duration) -> Duration {
+        let max = duration.as_ticks() * u64::from(self.percent) / 100;
+        if max == 0 {
+            return duration;
+        }
+        // Modulo bias is negligible against a 64 bit draw
+        let offset = self.rng.next_u64() % (2 * max + 1);
+        Duration::from_ticks(duration.as_ticks() - max + offset)
+    }
+}
+
+/// xorshift64; plenty for spreading tags out, no good for anything that has to be unpredictable.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// `timeout` for `peripheral::Config` to advertise for `remaining`, rounded up to the next 10ms.
+/// Anything longer than the softdevice allows (about 11 minutes) has to be done in more than one go.
+pub fn adv_timeout(remaining: Duration) -> u16 {
+    let units = remaining.as_millis().div_ceil(10);
+    units.clamp(1, MAX_ADV_TIMEOUT.into()) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_time::MockDriver;
+
+    #[test]
+    fn test_cycle() {
+        let duty_cycle = DutyCycle::new(AdvWindow::from_secs(10), 30);
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.phase(), Phase::Measure);
+
+        let start = Instant::now();
+        assert_eq!(
+            scheduler.advance(start, duty_cycle),
+            Phase::Advertise {
+                until: start + Duration::from_secs(10)
+            }
+        );
+
+        // The softdevice stopped a little after it was asked to; the sleep still gets its full 30s
+        MockDriver::get().advance(Duration::from_millis(10_020));
+        let now = Instant::now();
+        assert_eq!(
+            scheduler.advance(now, duty_cycle),
+            Phase::Sleep {
+                until: now + Duration::from_secs(30)
+            }
+        );
+
+        // Cut short by a button press
+        MockDriver::get().advance(Duration::from_secs(5));
+        assert_eq!(
+            scheduler.advance(Instant::now(), duty_cycle),
+            Phase::Measure
+        );
+    }
+
+    #[test]
+    fn test_jitter() {
+        let nominal = Duration::from_secs(10);
+        let mut jitter = Jitter::new(20, 0x1234_5678);
+        let mut total = Duration::from_ticks(0);
+        for _ in 0..10_000 {
+            let d = jitter.apply(nominal);
+            assert!(d >= Duration::from_secs(8) && d <= Duration::from_secs(12));
+            total += d;
+        }
+        // Evens out to well within 1%
+        let average = total / 10_000;
+        assert!(average > Duration::from_millis(9_900) && average < Duration::from_millis(10_100));
+
+        // Tags with different seeds go their own way
+        let mut other = Jitter::new(20, 0x1234_5679);
+        assert_ne!(
+            Jitter::new(20, 0x1234_5678).apply(nominal),
+            other.apply(nominal)
+        );
+
+        let mut none = Jitter::NONE;
+        assert_eq!(none.apply(nominal), nominal);
+        assert_eq!(Jitter::new(0, 1).apply(nominal), nominal);
+    }
+
+    #[test]
+    fn test_duty_cycle_limit() {
+        let duty_cycle = DutyCycle::new(AdvWindow::from_secs(60), u16::MAX);
+        assert_eq!(duty_cycle.on, Duration::from_secs(60));
+        assert_eq!(duty_cycle.off, MAX_PHASE);
+    }
+
+    #[test]
+    fn test_adv_timeout() {
+        assert_eq!(adv_timeout(Duration::from_secs(10)), 1000);
+        assert_eq!(adv_timeout(Duration::from_millis(1)), 1);
+        assert_eq!(adv_timeout(Duration::from_ticks(0)), 1);
+        assert_eq!(adv_timeout(Duration::from_secs(60 * 60)), u16::MAX);
+    }
+}
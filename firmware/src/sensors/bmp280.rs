@@ -0,0 +1,197 @@
+//! Bosch BMP280 barometric pressure sensor.
+//! Uses forced mode: one measurement per `measure()`, then the chip goes back to sleep (~0.1uA) on its own.
+//!
+//! Only pressure is reported; see the note in [`super::sht3x`] about temperature.
+//! The temperature reading is still needed internally to compensate the pressure reading.
+
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+
+use super::{Readings, Sensor};
+use crate::common::bthome::Object;
+
+/// 7 bit address with SDO tied low. Tie it high and the address is 0x77.
+pub const ADDRESS: u8 = 0x76;
+
+const CHIP_ID: u8 = 0xd0;
+const CHIP_ID_VALUE: u8 = 0x58;
+const CALIBRATION: u8 = 0x88;
+const CTRL_MEAS: u8 = 0xf4;
+const DATA: u8 = 0xf7;
+
+/// Temperature and pressure oversampling x1 (osrs_t 001, osrs_p 001), forced mode (01).
+/// Plenty for a pet tracker; nobody is using this as a weather station.
+const CTRL_MEAS_FORCED_X1: u8 = 0b0010_0101;
+/// Worst case for x1/x1 is 6.4ms
+const MEASURE_TIME_MS: u64 = 7;
+
+#[derive(Debug, defmt::Format)]
+pub enum Error<E> {
+    Bus(E),
+    /// CHIP_ID returned something other than 0x58
+    WrongDevice(u8),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Bus(e)
+    }
+}
+
+/// Factory trimming values; read once per measurement as the driver is not kept around between them.
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+}
+
+impl Calibration {
+    fn from_bytes(b: &[u8; 24]) -> Self {
+        let u = |i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([b[i], b[i + 1]]);
+        Self {
+            t1: u(0),
+            t2: s(2),
+            t3: s(4),
+            p1: u(6),
+            p2: s(8),
+            p3: s(10),
+            p4: s(12),
+            p5: s(14),
+            p6: s(16),
+            p7: s(18),
+            p8: s(20),
+            p9: s(22),
+        }
+    }
+
+    /// `bmp280_compensate_T_int32` from the datasheet; returns t_fine.
+    fn t_fine(&self, adc_t: i32) -> i32 {
+        let t1 = self.t1 as i32;
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * self.t2 as i32) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * self.t3 as i32) >> 14;
+        var1 + var2
+    }
+
+    /// `bmp280_compensate_P_int64` from the datasheet; returns Pa.
+    fn pressure_pa(&self, adc_p: i32, t_fine: i32) -> u32 {
+        let mut var1 = t_fine as i64 - 128000;
+        let mut var2 = var1 * var1 * self.p6 as i64;
+        var2 += (var1 * self.p5 as i64) << 17;
+        var2 += (self.p4 as i64) << 35;
+        var1 = ((var1 * var1 * self.p3 as i64) >> 8) + ((var1 * self.p2 as i64) << 12);
+        var1 = (((1i64 << 47) + var1) * self.p1 as i64) >> 33;
+        if var1 == 0 {
+            // Avoid a divide by zero
+            return 0;
+        }
+        let mut p = 1048576 - adc_p as i64;
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (self.p9 as i64 * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (self.p8 as i64 * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + ((self.p7 as i64) << 4);
+        // p is Pa in Q24.8
+        (p >> 8) as u32
+    }
+}
+
+pub struct Bmp280<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Bmp280<I> {
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<I: I2c> Sensor for Bmp280<I>
+where
+    I::Error: defmt::Format,
+{
+    type Error = Error<I::Error>;
+
+    async fn power_up(&mut self) -> Result<(), Self::Error> {
+        let mut id = [0; 1];
+        self.i2c
+            .write_read(self.address, &[CHIP_ID], &mut id)
+            .await?;
+        if id[0] != CHIP_ID_VALUE {
+            return Err(Error::WrongDevice(id[0]));
+        }
+        Ok(())
+    }
+
+    async fn measure(&mut self) -> Result<Readings, Self::Error> {
+        let mut calibration = [0; 24];
+        self.i2c
+            .write_read(self.address, &[CALIBRATION], &mut calibration)
+            .await?;
+        let calibration = Calibration::from_bytes(&calibration);
+
+        self.i2c
+            .write(self.address, &[CTRL_MEAS, CTRL_MEAS_FORCED_X1])
+            .await?;
+        Timer::after_millis(MEASURE_TIME_MS).await;
+
+        // press msb, lsb, xlsb, temp msb, lsb, xlsb
+        let mut data = [0; 6];
+        self.i2c
+            .write_read(self.address, &[DATA], &mut data)
+            .await?;
+        let adc_p = ((data[0] as i32) << 12) | ((data[1] as i32) << 4) | ((data[2] as i32) >> 4);
+        let adc_t = ((data[3] as i32) << 12) | ((data[4] as i32) << 4) | ((data[5] as i32) >> 4);
+
+        let t_fine = calibration.t_fine(adc_t);
+        // BTHome pressure is in 0.01 hPa which is conveniently just Pa
+        let pressure = calibration.pressure_pa(adc_p, t_fine);
+
+        let mut readings = Readings::new();
+        readings.push(Object::Pressure(pressure));
+        Ok(readings)
+    }
+
+    async fn power_down(&mut self) -> Result<(), Self::Error> {
+        // Forced mode drops back to sleep on its own after the measurement
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compensation() {
+        // Worked example from the datasheet
+        let calibration = Calibration {
+            t1: 27504,
+            t2: 26435,
+            t3: -1000,
+            p1: 36477,
+            p2: -10685,
+            p3: 3024,
+            p4: 2855,
+            p5: 140,
+            p6: -7,
+            p7: 15500,
+            p8: -14600,
+            p9: 6000,
+        };
+        let t_fine = calibration.t_fine(519888);
+        // 25.08 °C
+        assert_eq!((t_fine * 5 + 128) >> 8, 2508);
+        // 100653.27 Pa
+        assert_eq!(calibration.pressure_pa(415148, t_fine), 100653);
+    }
+}
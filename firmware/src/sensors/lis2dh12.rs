@@ -0,0 +1,115 @@
+//! Minimal driver for the ST LIS2DH12 accelerometer found on the DUOWEISI LS2DH tags.
+//! Only does what is needed to get a (latched) motion interrupt out of the chip on INT1.
+//! No FIFO, no raw samples.
+
+use embedded_hal_async::i2c::I2c;
+
+/// 7 bit address with SA0 tied low. Tie it high and the address is 0x19.
+pub const ADDRESS: u8 = 0x18;
+
+const WHO_AM_I: u8 = 0x0f;
+const WHO_AM_I_VALUE: u8 = 0x33;
+
+const CTRL_REG1: u8 = 0x20;
+const CTRL_REG2: u8 = 0x21;
+const CTRL_REG3: u8 = 0x22;
+const CTRL_REG4: u8 = 0x23;
+const CTRL_REG5: u8 = 0x24;
+const REFERENCE: u8 = 0x26;
+const INT1_CFG: u8 = 0x30;
+const INT1_SRC: u8 = 0x31;
+const INT1_THS: u8 = 0x32;
+const INT1_DURATION: u8 = 0x33;
+
+/// 10Hz ODR, low power mode, X/Y/Z enabled.
+/// 10Hz is plenty to notice that a dog is moving and keeps the chip at ~3uA.
+const CTRL_REG1_10HZ_LP_XYZ: u8 = 0x2f;
+/// Power down mode; all axes off.
+const CTRL_REG1_POWER_DOWN: u8 = 0x00;
+/// High pass filter on the INT1 path so gravity doesn't count as motion.
+const CTRL_REG2_HP_IA1: u8 = 0x01;
+/// Route interrupt activity 1 to the INT1 pin.
+const CTRL_REG3_I1_IA1: u8 = 0x40;
+/// +/- 2g full scale
+const CTRL_REG4_2G: u8 = 0x00;
+/// Latch INT1 until INT1_SRC is read.
+const CTRL_REG5_LIR_INT1: u8 = 0x08;
+/// OR of the X/Y/Z high events.
+const INT1_CFG_XYZ_HIGH: u8 = 0x2a;
+
+/// At +/- 2g, 1 LSB of INT1_THS is 16mg.
+const THRESHOLD_MG_PER_LSB: u16 = 16;
+
+#[derive(Debug, defmt::Format)]
+pub enum Error<E> {
+    Bus(E),
+    /// WHO_AM_I returned something other than 0x33
+    WrongDevice(u8),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Bus(e)
+    }
+}
+
+pub struct Lis2dh12<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Lis2dh12<I> {
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    /// Configures the chip to latch INT1 high whenever acceleration on any axis exceeds `threshold_mg`.
+    pub async fn enable_motion_interrupt(
+        &mut self,
+        threshold_mg: u16,
+    ) -> Result<(), Error<I::Error>> {
+        let who_am_i = self.read_register(WHO_AM_I).await?;
+        if who_am_i != WHO_AM_I_VALUE {
+            return Err(Error::WrongDevice(who_am_i));
+        }
+
+        let threshold = (threshold_mg / THRESHOLD_MG_PER_LSB).clamp(1, 0x7f) as u8;
+
+        self.write_register(CTRL_REG1, CTRL_REG1_10HZ_LP_XYZ)
+            .await?;
+        self.write_register(CTRL_REG2, CTRL_REG2_HP_IA1).await?;
+        self.write_register(CTRL_REG3, CTRL_REG3_I1_IA1).await?;
+        self.write_register(CTRL_REG4, CTRL_REG4_2G).await?;
+        self.write_register(CTRL_REG5, CTRL_REG5_LIR_INT1).await?;
+        self.write_register(INT1_THS, threshold).await?;
+        self.write_register(INT1_DURATION, 0).await?;
+        // Reading REFERENCE resets the high pass filter so we don't get an interrupt right away
+        self.read_register(REFERENCE).await?;
+        self.write_register(INT1_CFG, INT1_CFG_XYZ_HIGH).await?;
+        self.clear_interrupt().await?;
+        Ok(())
+    }
+
+    /// Reads INT1_SRC which releases the latched INT1 pin.
+    pub async fn clear_interrupt(&mut self) -> Result<u8, Error<I::Error>> {
+        self.read_register(INT1_SRC).await
+    }
+
+    /// Puts the chip into power down mode; no more motion interrupts after this.
+    pub async fn power_down(&mut self) -> Result<(), Error<I::Error>> {
+        self.write_register(CTRL_REG1, CTRL_REG1_POWER_DOWN).await
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, Error<I::Error>> {
+        let mut buf = [0; 1];
+        self.i2c
+            .write_read(self.address, &[register], &mut buf)
+            .await?;
+        Ok(buf[0])
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error<I::Error>> {
+        self.i2c.write(self.address, &[register, value]).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+//! Optional sensors that some of the tags ship with.
+//! Each driver implements [`Sensor`] so the main loop can treat them all the same way:
+//! power up, measure, hand back BTHome objects, power down.
+//! Drivers are gated behind a cargo feature of the same name; only enable what's on the board.
+//! The tests build all of them.
+
+use arrayvec::ArrayVec;
+
+use crate::common::bthome::Object;
+
+#[cfg(any(feature = "bmp280", test))]
+pub mod bmp280;
+#[cfg(any(feature = "lis2dh12", test))]
+pub mod lis2dh12;
+#[cfg(any(feature = "photodiode", test))]
+pub mod photodiode;
+#[cfg(any(feature = "sht3x", test))]
+pub mod sht3x;
+
+/// No sensor we support reports more than a couple of values.
+pub const MAX_OBJECTS: usize = 2;
+
+pub type Readings = ArrayVec<Object, MAX_OBJECTS>;
+
+#[allow(async_fn_in_trait)]
+pub trait Sensor {
+    type Error: defmt::Format;
+
+    /// Wake the sensor up / do whatever it needs before it can measure.
+    async fn power_up(&mut self) -> Result<(), Self::Error>;
+
+    /// Take a single measurement and encode it as BTHome objects.
+    async fn measure(&mut self) -> Result<Readings, Self::Error>;
+
+    /// Put the sensor back into its lowest power state.
+    async fn power_down(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Power up, measure, power down.
+/// The sensor is powered down even if the measurement fails.
+pub async fn sample<S: Sensor>(sensor: &mut S) -> Result<Readings, S::Error> {
+    sensor.power_up().await?;
+    let readings = sensor.measure().await;
+    sensor.power_down().await?;
+    readings
+}
@@ -0,0 +1,43 @@
+//! Analog ambient light sensor: a phototransistor (ALS-PT19 or similar) with a load resistor to ground,
+//! read on a spare SAADC channel.
+//! This shares the SAADC with the battery reading so it only ever samples during the measurement phase
+//! and there is nothing to power up or down; that's also why it isn't a [`super::Sensor`].
+
+/// Typical photocurrent per lux for the ALS-PT19; 20uA at 100 lux.
+pub const ALS_PT19_NA_PER_LUX: u32 = 200;
+
+/// SAADC full scale with the default gain (1/6) and internal reference (0.6V)
+const FULL_SCALE_MV: u64 = 3600;
+/// 10 bit samples
+const SAMPLE_RANGE: u64 = 1024;
+
+/// BTHome illuminance is a uint24
+const MAX_CENTILUX: u64 = 0xff_ffff;
+
+/// Converts a 10 bit SAADC sample to 0.01 lux, the unit BTHome wants for object 0x05.
+/// I = V / R and lux = I / (nA per lux).
+pub fn centilux(sample: i16, load_ohms: u32, na_per_lux: u32) -> u32 {
+    // Single ended samples can dip slightly below 0 in the dark
+    let sample = sample.max(0) as u64;
+    // mV -> nA is 1e6 / R, then x100 for centilux; combined into one division to keep the precision
+    let centilux = sample * FULL_SCALE_MV * 100_000_000
+        / (SAMPLE_RANGE * load_ohms as u64 * na_per_lux as u64);
+    centilux.min(MAX_CENTILUX) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_centilux() {
+        // Half scale is 1.8V; 180uA through 10k, which is 900 lux
+        assert_eq!(centilux(512, 10_000, ALS_PT19_NA_PER_LUX), 90_000);
+        // ~200mV; 20uA is 100 lux, give or take a step of the ADC
+        assert_eq!(centilux(57, 10_000, ALS_PT19_NA_PER_LUX), 10_019);
+        assert_eq!(centilux(0, 10_000, ALS_PT19_NA_PER_LUX), 0);
+        assert_eq!(centilux(-3, 10_000, ALS_PT19_NA_PER_LUX), 0);
+        // More than a uint24 can hold
+        assert_eq!(centilux(1023, 10, ALS_PT19_NA_PER_LUX), 0xff_ffff);
+    }
+}
@@ -0,0 +1,117 @@
+//! Sensirion SHT30/31/35 temperature and humidity sensor.
+//! Uses single shot mode; between measurements the chip idles at ~0.2uA on its own so there is
+//! nothing to do for power up/down.
+//!
+//! Only humidity is reported. Temperature already comes from the die and a second temperature
+//! object would show up in Home Assistant as a separate, near identical, entity.
+
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+
+use super::{Readings, Sensor};
+use crate::common::bthome::Object;
+
+/// 7 bit address with ADDR tied low. Tie it high and the address is 0x45.
+pub const ADDRESS: u8 = 0x44;
+
+/// Single shot, high repeatability, no clock stretching
+const MEASURE_HIGH_REPEATABILITY: [u8; 2] = [0x24, 0x00];
+/// Worst case for high repeatability is 15.5ms
+const MEASURE_TIME_MS: u64 = 16;
+
+#[derive(Debug, defmt::Format)]
+pub enum Error<E> {
+    Bus(E),
+    /// Sensor data did not match its CRC
+    Crc,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Bus(e)
+    }
+}
+
+pub struct Sht3x<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Sht3x<I> {
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<I: I2c> Sensor for Sht3x<I>
+where
+    I::Error: defmt::Format,
+{
+    type Error = Error<I::Error>;
+
+    async fn power_up(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn measure(&mut self) -> Result<Readings, Self::Error> {
+        self.i2c
+            .write(self.address, &MEASURE_HIGH_REPEATABILITY)
+            .await?;
+        Timer::after_millis(MEASURE_TIME_MS).await;
+
+        // temperature msb, lsb, crc, humidity msb, lsb, crc
+        let mut buf = [0; 6];
+        self.i2c.read(self.address, &mut buf).await?;
+        if crc8(&buf[3..5]) != buf[5] {
+            return Err(Error::Crc);
+        }
+
+        let raw_humidity = u16::from_be_bytes([buf[3], buf[4]]);
+        let mut readings = Readings::new();
+        readings.push(Object::Humidity(centi_percent(raw_humidity)));
+        Ok(readings)
+    }
+
+    async fn power_down(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// RH = 100 * raw / (2^16 - 1), scaled to the 0.01% BTHome wants.
+fn centi_percent(raw_humidity: u16) -> u16 {
+    (raw_humidity as u32 * 10000 / 0xffff) as u16
+}
+
+/// CRC-8, polynomial 0x31, init 0xff. From the datasheet.
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0xff;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc8() {
+        // Example from the datasheet
+        assert_eq!(crc8(&[0xbe, 0xef]), 0x92);
+    }
+
+    #[test]
+    fn test_centi_percent() {
+        assert_eq!(centi_percent(0), 0);
+        assert_eq!(centi_percent(0xffff), 10000);
+        assert_eq!(centi_percent(0x8000), 5000);
+    }
+}
@@ -0,0 +1,70 @@
+//! "Shelf mode": System OFF, the lowest power state there is, for tags sitting in a box.
+//! Entered by holding the button down for [`HOLD`](crate::common::button::HOLD), by writing
+//! [`MAGIC`] to the config window's shelf characteristic, or after a stretch with no motion
+//! (`BTHP_SHELF_AFTER_MINS`).
+//!
+//! Nothing runs until GPIO SENSE sees the button pressed or the accelerometer's motion interrupt,
+//! and waking up is a reset; the tag boots like it was just powered up.
+//! Waking on motion needs the accelerometer left running, which costs more than the chip does in
+//! System OFF; with the accelerometer off in the active profile, only the button wakes the tag.
+
+use defmt::{info, warn};
+use nrf_softdevice::raw;
+
+/// "SHLF", little endian
+pub const MAGIC: u32 = 0x464c_4853;
+
+/// P0 PIN_CNF[0..32]; the 52810 and 52832 only have the one port
+const P0_PIN_CNF: *mut u32 = 0x5000_0700 as *mut u32;
+/// PIN_CNF fields; input, connected, standard drive
+const PULL_UP: u32 = 3 << 2;
+const SENSE_HIGH: u32 = 2 << 16;
+const SENSE_LOW: u32 = 3 << 16;
+
+/// Pins (P0 numbers) that can wake the tag back up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct WakeSources {
+    /// Wired to ground; wakes on low
+    pub button: Option<u8>,
+    /// Accelerometer interrupt, active high; the interrupt has to be set up and cleared already
+    pub motion: Option<u8>,
+}
+
+impl WakeSources {
+    /// Without any, the only way back is taking the battery out.
+    pub fn any(&self) -> bool {
+        self.button.is_some() || self.motion.is_some()
+    }
+}
+
+/// Goes to System OFF until one of `wake` fires. Only returns if there's nothing to wake on.
+pub fn enter(wake: WakeSources) {
+    if !wake.any() {
+        warn!("shelf: nothing to wake up on; staying on");
+        return;
+    }
+    off(wake);
+}
+
+/// Goes to System OFF until one of `wake` fires; with none, until the battery comes out.
+/// For when staying on isn't an option, like a supply about to give out.
+pub fn off(wake: WakeSources) -> ! {
+    info!("shelf: off until {}", wake);
+    if let Some(pin) = wake.button {
+        sense(pin, PULL_UP | SENSE_LOW);
+    }
+    if let Some(pin) = wake.motion {
+        sense(pin, SENSE_HIGH);
+    }
+    // SAFETY: no arguments; the softdevice is enabled
+    let ret = unsafe { raw::sd_power_system_off() };
+    // System OFF is only emulated with a debugger attached, and the call returns
+    defmt::panic!("shelf: sd_power_system_off returned {}", ret);
+}
+
+/// Reconfigures `pin` as an input with `cnf` (pull and sense) so it can wake the chip.
+fn sense(pin: u8, cnf: u32) {
+    assert!(pin < 32);
+    // SAFETY: a single aligned word in range; whatever was using the pin is never coming back
+    unsafe { core::ptr::write_volatile(P0_PIN_CNF.add(pin.into()), cnf) }
+}
@@ -0,0 +1,143 @@
+//! Die temperature from the nRF52 TEMP peripheral.
+//! The softdevice owns TEMP so the reading has to go through `sd_temp_get()`.
+//!
+//! The datasheet correction is done by TEMP itself: it linearises each reading piecewise with its
+//! A0-A5, B0-B5 and T0-T4 registers. Their reset values don't meet the accuracy spec (anomaly 66 in
+//! the nRF52832 and nRF52810 errata); the fix is to load the calibrated values from FICR, which is
+//! what [`load_linearisation`] does at boot. After that the reading is already corrected, in 0.25°C
+//! steps, and [`centi_celsius`] only has to scale it.
+
+#[cfg(feature = "nrf52")]
+use nrf_softdevice::{raw, RawError, Softdevice};
+
+/// Nordic recommends re-running SAADC offset calibration once the temperature
+/// has moved by 10°C since the last calibration; in centi-degrees.
+const SAADC_RECALIBRATION_DELTA: u16 = 1000;
+
+/// FICR.TEMP: A0-A5, B0-B5 then T0-T4, one word each
+#[cfg(feature = "nrf52")]
+const FICR_TEMP: *const [u32; 17] = 0x1000_0404 as *const [u32; 17];
+/// TEMP.A0, TEMP.B0 and TEMP.T0; each group is contiguous but there are gaps between them
+#[cfg(feature = "nrf52")]
+const TEMP_A0: *mut u32 = 0x4000_c520 as *mut u32;
+#[cfg(feature = "nrf52")]
+const TEMP_B0: *mut u32 = 0x4000_c540 as *mut u32;
+#[cfg(feature = "nrf52")]
+const TEMP_T0: *mut u32 = 0x4000_c560 as *mut u32;
+
+/// Anomaly 66 workaround: copies the calibrated linearisation from FICR into TEMP.
+/// Has to happen before the softdevice is enabled; after that TEMP is off limits.
+#[cfg(feature = "nrf52")]
+pub fn load_linearisation() {
+    // SAFETY: FICR is always mapped and read only. Nothing else touches TEMP this early; the
+    // softdevice isn't enabled yet and embassy doesn't use it.
+    unsafe {
+        let ficr = core::ptr::read_volatile(FICR_TEMP);
+        for (i, &a) in ficr[0..6].iter().enumerate() {
+            core::ptr::write_volatile(TEMP_A0.add(i), a);
+        }
+        for (i, &b) in ficr[6..12].iter().enumerate() {
+            core::ptr::write_volatile(TEMP_B0.add(i), b);
+        }
+        for (i, &t) in ficr[12..17].iter().enumerate() {
+            core::ptr::write_volatile(TEMP_T0.add(i), t);
+        }
+    }
+}
+
+/// Reads the die temperature in 0.01°C, the unit BTHome uses for object 0x02.
+/// `_sd` isn't used; asking for it makes sure the softdevice is enabled, which `sd_temp_get()` needs.
+#[cfg(feature = "nrf52")]
+pub fn read_centi_celsius(_sd: &Softdevice) -> Result<i16, RawError> {
+    let mut raw_temp: i32 = 0;
+    // SAFETY: `raw_temp` outlives the call; the softdevice only writes the result to it
+    let ret = unsafe { raw::sd_temp_get(&mut raw_temp) };
+    RawError::convert(ret)?;
+    Ok(centi_celsius(raw_temp))
+}
+
+/// The TEMP register counts in 0.25°C steps (datasheet, TEMP section) so each step is 25 centi-degrees.
+/// It's already linearised; see the module docs.
+/// The die is rated for -40°C to 85°C which fits in an i16 with plenty of room to spare.
+pub fn centi_celsius(raw_temp: i32) -> i16 {
+    raw_temp
+        .saturating_mul(25)
+        .clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+/// Decides when the SAADC should be re-calibrated based on the die temperature.
+/// Calibration costs a few hundred uS of the ADC running so we only do it when it matters.
+pub struct SaadcCalibration {
+    calibrated_at: Option<i16>,
+}
+
+impl SaadcCalibration {
+    pub const fn new() -> Self {
+        Self {
+            calibrated_at: None,
+        }
+    }
+
+    /// Returns true if the SAADC should be calibrated before sampling.
+    /// Always true for the first sample and whenever the temperature could not be read.
+    pub fn is_due(&mut self, temperature: Option<i16>) -> bool {
+        let due = match (self.calibrated_at, temperature) {
+            (Some(then), Some(now)) => now.abs_diff(then) >= SAADC_RECALIBRATION_DELTA,
+            _ => true,
+        };
+        if due {
+            self.calibrated_at = temperature;
+        }
+        due
+    }
+}
+
+impl Default for SaadcCalibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_centi_celsius() {
+        assert_eq!(centi_celsius(0), 0);
+        // 25°C
+        assert_eq!(centi_celsius(100), 2500);
+        assert_eq!(centi_celsius(-160), -4000);
+        assert_eq!(centi_celsius(1), 25);
+        assert_eq!(centi_celsius(i32::MAX), i16::MAX);
+        assert_eq!(centi_celsius(i32::MIN), i16::MIN);
+    }
+
+    #[test]
+    fn test_is_due() {
+        let mut calibration = SaadcCalibration::new();
+        assert!(calibration.is_due(Some(2000)));
+        assert!(!calibration.is_due(Some(2000)));
+        // Just short of the delta, either way
+        assert!(!calibration.is_due(Some(2999)));
+        assert!(!calibration.is_due(Some(1001)));
+        // Right on it
+        assert!(calibration.is_due(Some(3000)));
+        // Measured from the last calibration, not the last reading
+        assert!(!calibration.is_due(Some(2001)));
+        assert!(calibration.is_due(Some(2000)));
+    }
+
+    #[test]
+    fn test_is_due_without_temperature() {
+        let mut calibration = SaadcCalibration::new();
+        assert!(calibration.is_due(None));
+        assert!(calibration.is_due(None));
+        // Nothing to compare against yet
+        assert!(calibration.is_due(Some(2000)));
+        assert!(!calibration.is_due(Some(2500)));
+        // Can't tell, so calibrate; and start over from the next reading
+        assert!(calibration.is_due(None));
+        assert!(calibration.is_due(Some(2500)));
+    }
+}
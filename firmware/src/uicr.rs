@@ -0,0 +1,218 @@
+//! The UICR CUSTOMER registers we use. They're written with the probe, not by the firmware.
+//! Clearing has to happen before the softdevice is enabled; after that NVMC belongs to it.
+//!
+//! CUSTOMER[0] is the factory reset request. From CUSTOMER[1] on is a per-device provisioning
+//! record made by `provisioning/provision.py`, laid out like the config record:
+//!
+//! ```text
+//! magic "BU" (2) | version (1) | body length (1) | body (n) | crc16 (2)
+//! ```
+//!
+//! v1 body, 32 bytes; each field is only used if its bit is set in `present`:
+//!
+//! ```text
+//! present (1) | bind key (16) | name prefix (8) | battery profile (1) | static address (6)
+//! ```
+
+use crate::common::battery::BatteryProfile;
+use crate::common::config::{is_static_address, Config, DecodeError, NAME_PREFIX_LEN};
+use crate::common::util::crc::crc16;
+
+/// Index into CUSTOMER[] of the factory reset request; see [`factory_reset`](crate::common::factory_reset)
+pub const FACTORY_RESET: usize = 0;
+/// Index into CUSTOMER[] of the start of the provisioning record
+pub const PROVISIONING: usize = 1;
+
+/// UICR CUSTOMER[0..32]; same place on the 52810 and 52832
+const CUSTOMER: *mut u32 = 0x1000_1080 as *mut u32;
+const CUSTOMER_LEN: usize = 32;
+
+const NVMC_READY: *const u32 = 0x4001_e400 as *const u32;
+const NVMC_CONFIG: *mut u32 = 0x4001_e504 as *mut u32;
+const NVMC_ERASEPAGE: *mut u32 = 0x4001_e508 as *mut u32;
+/// NVMC CONFIG values
+const NVMC_REN: u32 = 0;
+const NVMC_WEN: u32 = 1;
+const NVMC_EEN: u32 = 2;
+
+const MAGIC: [u8; 2] = *b"BU";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 4;
+const CRC_LEN: usize = 2;
+const BODY_LEN: usize = 32;
+const RECORD_LEN: usize = HEADER_LEN + BODY_LEN + CRC_LEN;
+
+/// Bits in `present`
+const HAS_BIND_KEY: u8 = 1 << 0;
+const HAS_NAME_PREFIX: u8 = 1 << 1;
+const HAS_BATTERY_PROFILE: u8 = 1 << 2;
+const HAS_STATIC_ADDRESS: u8 = 1 << 3;
+
+/// Per-device values from the provisioning record; anything not provisioned is `None`.
+/// Deliberately not `defmt::Format`; the bind key has no business being in the logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Provisioning {
+    /// Not used until encrypted adverts are; kept so tags don't have to be provisioned twice
+    pub bind_key: Option<[u8; 16]>,
+    /// ASCII, padded with 0
+    pub name_prefix: Option<[u8; NAME_PREFIX_LEN]>,
+    pub battery_profile: Option<BatteryProfile>,
+    /// Least significant byte first
+    pub static_address: Option<[u8; 6]>,
+}
+
+impl Provisioning {
+    /// Reads the record out of UICR; a tag that was never provisioned has nothing to offer.
+    pub fn read() -> Result<Self, DecodeError> {
+        let mut record = [0; RECORD_LEN];
+        for (i, word) in record.chunks_exact_mut(4).enumerate() {
+            word.copy_from_slice(&customer(PROVISIONING + i).to_le_bytes());
+        }
+        // RECORD_LEN isn't a multiple of 4; pick up the last 2 bytes of the CRC
+        let last = RECORD_LEN / 4;
+        record[last * 4..].copy_from_slice(&customer(PROVISIONING + last).to_le_bytes()[..2]);
+        Self::decode(&record)
+    }
+
+    pub fn decode(record: &[u8]) -> Result<Self, DecodeError> {
+        if record.len() < RECORD_LEN {
+            return Err(DecodeError::BadLength);
+        }
+        if record[..HEADER_LEN].iter().all(|&b| b == 0xff) {
+            return Err(DecodeError::Blank);
+        }
+        if record[0..2] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        if record[2] != VERSION {
+            return Err(DecodeError::UnknownVersion(record[2]));
+        }
+        if record[3] as usize != BODY_LEN {
+            return Err(DecodeError::BadLength);
+        }
+        let crc_at = HEADER_LEN + BODY_LEN;
+        let crc = u16::from_le_bytes([record[crc_at], record[crc_at + 1]]);
+        if crc != crc16(&record[..crc_at]) {
+            return Err(DecodeError::BadCrc);
+        }
+
+        let body = &record[HEADER_LEN..crc_at];
+        let present = body[0];
+        let mut provisioning = Self::default();
+        if present & HAS_BIND_KEY != 0 {
+            let mut key = [0; 16];
+            key.copy_from_slice(&body[1..17]);
+            provisioning.bind_key = Some(key);
+        }
+        if present & HAS_NAME_PREFIX != 0 {
+            let mut prefix = [0; NAME_PREFIX_LEN];
+            prefix.copy_from_slice(&body[17..25]);
+            provisioning.name_prefix = Some(prefix);
+        }
+        if present & HAS_BATTERY_PROFILE != 0 {
+            provisioning.battery_profile = Some(match body[25] {
+                0 => BatteryProfile::Linear,
+                1 => BatteryProfile::Cr2032,
+                _ => return Err(DecodeError::BadValue),
+            });
+        }
+        if present & HAS_STATIC_ADDRESS != 0 {
+            let mut address = [0; 6];
+            address.copy_from_slice(&body[26..32]);
+            if !is_static_address(&address) {
+                return Err(DecodeError::BadValue);
+            }
+            provisioning.static_address = Some(address);
+        }
+        Ok(provisioning)
+    }
+
+    /// `defaults` with whatever was provisioned on top.
+    pub fn apply(&self, defaults: Config) -> Config {
+        Config {
+            name_prefix: self.name_prefix.unwrap_or(defaults.name_prefix),
+            static_address: self.static_address.or(defaults.static_address),
+            ..defaults
+        }
+    }
+}
+
+/// Reads CUSTOMER[`index`]; 0xffffffff if it was never written.
+pub fn customer(index: usize) -> u32 {
+    assert!(index < CUSTOMER_LEN);
+    // SAFETY: UICR is always mapped and in range
+    unsafe { core::ptr::read_volatile(CUSTOMER.add(index)) }
+}
+
+/// Zeroes CUSTOMER[`index`] so whatever it asked for isn't done again on the next boot.
+/// Going to 0 only clears bits so the rest of UICR doesn't have to be erased.
+pub fn clear_customer(index: usize) {
+    assert!(index < CUSTOMER_LEN);
+    // SAFETY: NVMC isn't used by anything else until the softdevice is enabled;
+    // the write is a single aligned word in range
+    unsafe {
+        core::ptr::write_volatile(NVMC_CONFIG, NVMC_WEN);
+        while core::ptr::read_volatile(NVMC_READY) == 0 {}
+        core::ptr::write_volatile(CUSTOMER.add(index), 0);
+        while core::ptr::read_volatile(NVMC_READY) == 0 {}
+        core::ptr::write_volatile(NVMC_CONFIG, NVMC_REN);
+    }
+}
+
+/// Erases the flash page starting at `address` the same way, for acting on a request before the
+/// softdevice is up.
+pub fn erase_page(address: u32) {
+    // SAFETY: as above; ERASEPAGE takes the address of the page and nothing runs from it
+    unsafe {
+        core::ptr::write_volatile(NVMC_CONFIG, NVMC_EEN);
+        while core::ptr::read_volatile(NVMC_READY) == 0 {}
+        core::ptr::write_volatile(NVMC_ERASEPAGE, address);
+        while core::ptr::read_volatile(NVMC_READY) == 0 {}
+        core::ptr::write_volatile(NVMC_CONFIG, NVMC_REN);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `provision.py --name-prefix DOG_ --battery-profile cr2032 --static-address e2:db:e8:62:67:0d`
+    const RECORD: [u8; RECORD_LEN] = [
+        0x42, 0x55, 0x01, 0x20, 0x0e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x44, 0x4f, 0x47, 0x5f, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x0d, 0x67, 0x62, 0xe8, 0xdb, 0xe2, 0x32, 0xd5,
+    ];
+
+    #[test]
+    fn test_decode() {
+        let provisioning = Provisioning::decode(&RECORD).unwrap();
+        assert_eq!(
+            provisioning,
+            Provisioning {
+                bind_key: None,
+                name_prefix: Some(*b"DOG_\0\0\0\0"),
+                battery_profile: Some(BatteryProfile::Cr2032),
+                static_address: Some([0x0d, 0x67, 0x62, 0xe8, 0xdb, 0xe2]),
+            }
+        );
+
+        let config = provisioning.apply(Config::default());
+        assert_eq!(config.name_prefix(), "DOG_");
+        assert_eq!(config.static_address, provisioning.static_address);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(
+            Provisioning::decode(&[0xff; RECORD_LEN]),
+            Err(DecodeError::Blank)
+        );
+        assert_eq!(
+            Provisioning::decode(&RECORD[..RECORD_LEN - 1]),
+            Err(DecodeError::BadLength)
+        );
+        let mut corrupt = RECORD;
+        corrupt[HEADER_LEN + 17] = b'C';
+        assert_eq!(Provisioning::decode(&corrupt), Err(DecodeError::BadCrc));
+    }
+}
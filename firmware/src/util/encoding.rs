@@ -1,9 +1,12 @@
+use arrayvec::ArrayString;
+
 /// Converts a single nibble (4 bits) to a hexadecimal character.
 fn nibble_to_hex_char(nibble: u8) -> char {
     match nibble {
         0x0..=0x9 => (b'0' + nibble) as char,
         0xa..=0xf => (b'a' + nibble - 10) as char,
-        _ => '?', // Should never happen if used correctly
+        // Callers mask off the nibble first
+        _ => panic!("not a nibble"),
     }
 }
 
@@ -14,6 +17,75 @@ pub fn byte_to_hex(byte: u8) -> [char; 2] {
     [nibble_to_hex_char(high), nibble_to_hex_char(low)]
 }
 
+/// Longest name that fits in a legacy advertisement: 31 bytes less the flags (3) and the name record header (2).
+/// Next to a full BTHome payload there's only room for 8 of these; anything longer goes out shortened.
+pub const MAX_NAME_LEN: usize = 26;
+
+/// Where the hex digits at the end of the device name come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum NameSource {
+    /// The BLE address; changes if the address does
+    Address,
+    /// FICR DEVICEID; fixed at the factory
+    DeviceId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum HexCase {
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum NameError {
+    /// The name would be this many characters
+    TooLong(usize),
+    /// Asked for more suffix bytes than the source has
+    NotEnoughBytes,
+}
+
+/// `prefix` followed by the bottom `suffix_bytes` bytes of `source` in hex; BTHPT_670d for
+/// the address e2:db:e8:62:67:0d with 2 bytes, lower case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NameTemplate<'a> {
+    pub prefix: &'a str,
+    pub suffix_bytes: u8,
+    pub source: NameSource,
+    pub case: HexCase,
+}
+
+impl NameTemplate<'_> {
+    /// Length of the name this makes.
+    pub fn name_len(&self) -> usize {
+        self.prefix.len() + 2 * self.suffix_bytes as usize
+    }
+
+    /// Builds the name from the source's bytes, least significant first; that's how both the
+    /// softdevice hands out the address and how DEVICEID sits in memory.
+    pub fn render(&self, source_bytes: &[u8]) -> Result<ArrayString<MAX_NAME_LEN>, NameError> {
+        let suffix_bytes = self.suffix_bytes as usize;
+        if self.name_len() > MAX_NAME_LEN {
+            return Err(NameError::TooLong(self.name_len()));
+        }
+        if suffix_bytes > source_bytes.len() {
+            return Err(NameError::NotEnoughBytes);
+        }
+
+        let mut name = ArrayString::new();
+        name.push_str(self.prefix);
+        // Most significant first, the way addresses are usually written
+        for &byte in source_bytes[..suffix_bytes].iter().rev() {
+            for c in byte_to_hex(byte) {
+                name.push(match self.case {
+                    HexCase::Lower => c,
+                    HexCase::Upper => c.to_ascii_uppercase(),
+                });
+            }
+        }
+        Ok(name)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // TODO: paths are a bit wonky, still need to fix them
@@ -42,4 +114,48 @@ mod tests {
         assert_eq!(byte_to_hex(0xff), ['f', 'f']);
         assert_eq!(byte_to_hex(0xab), ['a', 'b']);
     }
+
+    #[test]
+    fn test_name_template() {
+        // e2:db:e8:62:67:0d as the softdevice hands it over
+        let address = [0x0d, 0x67, 0x62, 0xe8, 0xdb, 0xe2];
+        let mut template = NameTemplate {
+            prefix: "BTHPT_",
+            suffix_bytes: 2,
+            source: NameSource::Address,
+            case: HexCase::Lower,
+        };
+        assert_eq!(template.render(&address).unwrap().as_str(), "BTHPT_670d");
+
+        template.case = HexCase::Upper;
+        template.suffix_bytes = 3;
+        assert_eq!(template.render(&address).unwrap().as_str(), "BTHPT_62670D");
+
+        template.prefix = "";
+        template.suffix_bytes = 0;
+        assert_eq!(template.render(&address).unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn test_name_template_errors() {
+        let device_id = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef];
+        let mut template = NameTemplate {
+            prefix: "BTHPT_",
+            suffix_bytes: 8,
+            source: NameSource::DeviceId,
+            case: HexCase::Lower,
+        };
+        assert_eq!(
+            template.render(&device_id).unwrap().as_str(),
+            "BTHPT_efcdab8967452301"
+        );
+        // Only 6 bytes in an address
+        assert_eq!(
+            template.render(&device_id[..6]),
+            Err(NameError::NotEnoughBytes)
+        );
+
+        template.prefix = "A_VERY_LONG_PREFIX";
+        assert_eq!(template.render(&device_id), Err(NameError::TooLong(34)));
+    }
 }
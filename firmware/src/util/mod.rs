@@ -1 +1,2 @@
+pub mod crc;
 pub mod encoding;
@@ -0,0 +1,75 @@
+//! Hardware watchdog; resets the chip if the main loop stops making progress.
+//! A task of its own feeds it every [`FEED_EVERY`], but only while the main loop is on time: before
+//! each wait the main loop says when it'll be back ([`expect_back_by`]), and once that's more than
+//! [`SLACK`] ago the feeding stops. That keeps [`TIMEOUT`] short no matter how long the sleeps and
+//! advertising windows are. It's paused while a debugger has the CPU halted.
+
+use core::cell::Cell;
+
+use defmt::{info, warn};
+use embassy_executor::Spawner;
+use embassy_nrf::peripherals::WDT;
+use embassy_nrf::wdt::{self, Watchdog, WatchdogHandle};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+
+/// How long the chip goes unfed before it resets.
+pub const TIMEOUT: Duration = Duration::from_secs(2 * 60);
+/// How often the feeder task wakes up; a few times per timeout so one late wake up doesn't matter
+const FEED_EVERY: Duration = Duration::from_secs(30);
+/// How late the main loop can be; covers the work between waits (sensors, flash, factory reset)
+pub const SLACK: Duration = Duration::from_secs(60);
+
+const _: () = assert!(FEED_EVERY.as_ticks() * 2 < TIMEOUT.as_ticks());
+
+/// WDT counts at 32.768kHz
+const WDT_TICK_HZ: u64 = 32_768;
+
+/// When the main loop said it would be back
+static BACK_BY: Mutex<CriticalSectionRawMutex, Cell<Instant>> =
+    Mutex::new(Cell::new(Instant::from_ticks(0)));
+
+/// Starts the watchdog and the task that feeds it; the main loop has [`SLACK`] to check in.
+/// A watchdog still running from before a soft reset can't be reconfigured; that one is taken over as is.
+pub fn start(spawner: Spawner, wdt: WDT) {
+    let config = match wdt::Config::try_new(&wdt) {
+        Some(running) => {
+            warn!("watchdog: already running; keeping its settings");
+            running
+        }
+        None => {
+            let mut config = wdt::Config::default();
+            config.timeout_ticks = (TIMEOUT.as_secs() * WDT_TICK_HZ) as u32;
+            // Sleeping is what we do most; a hang there has to be caught too
+            config.run_during_sleep = true;
+            // Otherwise sitting at a breakpoint resets the chip
+            config.run_during_debug_halt = false;
+            config
+        }
+    };
+    let (_wdt, [handle]) = match Watchdog::try_new(wdt, config) {
+        Ok(wdt) => wdt,
+        // try_new only fails on a mismatch with the running config, which we just read back
+        Err(_) => defmt::panic!("watchdog: unable to start"),
+    };
+    info!("watchdog: {}s", TIMEOUT.as_secs());
+    expect_back_by(Instant::now());
+    defmt::unwrap!(spawner.spawn(feeder(handle)));
+}
+
+/// The main loop is about to wait until `deadline` and will check in again then; up to [`SLACK`]
+/// later is still on time.
+pub fn expect_back_by(deadline: Instant) {
+    BACK_BY.lock(|back_by| back_by.set(deadline + SLACK));
+}
+
+#[embassy_executor::task]
+async fn feeder(mut handle: WatchdogHandle) {
+    loop {
+        if Instant::now() <= BACK_BY.lock(Cell::get) {
+            handle.pet();
+        }
+        Timer::after(FEED_EVERY).await;
+    }
+}